@@ -0,0 +1,99 @@
+use crate::book::Book;
+use crate::check::book_markers;
+use crate::check::describe_place;
+use crate::check::neighbours;
+use crate::config::Config;
+use crate::repo::CommitResolver;
+use crate::repo::GitRepo;
+use crate::skip::SkipRules;
+use crate::Result;
+use argh::FromArgs;
+use std::path::PathBuf;
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Find where the commit of a line of code is explained in the book
+#[argh(subcommand, name = "blame")]
+pub struct Args {
+    /// git repo for commits
+    #[argh(option)]
+    code: PathBuf,
+    /// config file (default: ajimi.toml if it exists)
+    #[argh(option)]
+    config: Option<PathBuf>,
+    /// line of the code as `path:line`, e.g. src/main.rs:12
+    #[argh(positional, from_str_fn(parse_location))]
+    location: (String, usize),
+    /// markdown files of the book
+    #[argh(positional)]
+    files: Vec<String>,
+}
+impl Args {
+    pub fn run(&self) -> Result<()> {
+        let repo = GitRepo::new(self.code.clone());
+        let skip = Config::find(self.config.as_deref())?.skip;
+        let book = Book::load(&self.files)?;
+        let (file, line) = &self.location;
+        println!("{}", blame(&book, &repo, &skip, file, *line)?);
+        Ok(())
+    }
+}
+
+fn parse_location(value: &str) -> std::result::Result<(String, usize), String> {
+    value
+        .rsplit_once(':')
+        .and_then(|(path, line)| Some((path.to_string(), line.parse().ok()?)))
+        .filter(|(path, line)| !path.is_empty() && *line > 0)
+        .ok_or_else(|| format!("expected path:line, got {value:?}"))
+}
+
+/// Describes where the commit that last changed `line` of `file` is explained
+/// in `book`, or why it is not.
+pub fn blame<T: CommitResolver>(
+    book: &Book,
+    repo: &T,
+    skip: &SkipRules,
+    file: &str,
+    line: usize,
+) -> Result<String> {
+    let hash = repo.blame_line(file, line)?;
+    let commits = repo.all_commit_summary_in_tree()?;
+    let Some(order) = commits.iter().rev().position(|c| c.hash == hash) else {
+        let short = &hash[..hash.len().min(12)];
+        return Ok(format!(
+            "{file}:{line} comes from {short}, which has no Change-Id"
+        ));
+    };
+    let c = &commits[commits.len() - 1 - order];
+    let markers = book_markers(book, &commits);
+    if let Some(marker) = markers.iter().find(|m| m.order == Some(order)) {
+        return Ok(format!("{file}:{line} comes from {marker}"));
+    }
+    let commit = format!("{} {:?}", c.change_id, c.title);
+    if let Some(reason) = skip.reason(c)? {
+        return Ok(format!(
+            "{file}:{line} comes from {commit}, which is skipped ({reason})"
+        ));
+    }
+    let mut message = format!("{file}:{line} comes from {commit}, which is not in the book");
+    let (prev, next) = neighbours(&markers, order);
+    if let Some(place) = describe_place(prev, next) {
+        message += &format!("\n  insert it {place}");
+    }
+    Ok(message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_locations() {
+        assert_eq!(
+            parse_location("src/a:b.rs:12"),
+            Ok(("src/a:b.rs".to_string(), 12))
+        );
+        assert!(parse_location("src/main.rs").is_err());
+        assert!(parse_location("src/main.rs:0").is_err());
+        assert!(parse_location(":3").is_err());
+    }
+}
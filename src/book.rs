@@ -0,0 +1,180 @@
+use crate::check::check_book;
+use crate::check::CheckOptions;
+use crate::check::Diagnostic;
+use crate::document::Document;
+use crate::fix::fix_document;
+use crate::fix::insert_missing_markers;
+use crate::fix::remap_change_ids;
+use crate::fix::FixOptions;
+use crate::repo::CommitResolver;
+use crate::text::TextFile;
+use crate::Result;
+use similar::TextDiff;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// A Markdown file of a book, with the changes made to it in memory.
+#[derive(Debug)]
+pub struct BookFile {
+    path: PathBuf,
+    file: TextFile,
+    doc: Document,
+}
+impl BookFile {
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = TextFile::read(path)?;
+        let doc = Document::parse(&file.content);
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            doc,
+        })
+    }
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    /// The path as given to [`Book::load`], for messages.
+    pub fn name(&self) -> String {
+        self.path.to_string_lossy().to_string()
+    }
+    pub fn document(&self) -> &Document {
+        &self.doc
+    }
+    pub fn document_mut(&mut self) -> &mut Document {
+        &mut self.doc
+    }
+    /// True if the document differs from the file on disk.
+    pub fn is_changed(&self) -> bool {
+        self.doc.to_string() != self.file.content
+    }
+    /// Unified diff from the file on disk to the document.
+    pub fn diff(&self) -> String {
+        let name = self.name();
+        TextDiff::from_lines(&self.file.content, &self.doc.to_string())
+            .unified_diff()
+            .header(&format!("a/{name}"), &format!("b/{name}"))
+            .to_string()
+    }
+    /// Writes the document back to the file if it was changed, keeping the
+    /// encoding of the original file.
+    pub fn save(&mut self) -> Result<()> {
+        let content = self.doc.to_string();
+        if content != self.file.content {
+            self.file.write(&self.path, &content)?;
+            self.file.content = content;
+        }
+        Ok(())
+    }
+}
+
+/// The Markdown files of a book, in reading order.
+///
+/// ```no_run
+/// use ajimi::Book;
+/// use ajimi::CheckOptions;
+/// use ajimi::FixOptions;
+/// use ajimi::GitRepo;
+///
+/// let repo = GitRepo::new("path/to/code".into());
+/// let mut book = Book::load(["src/ch01.md", "src/ch02.md"])?;
+/// for diagnostic in book.check(&repo, &CheckOptions::default())? {
+///     println!("{diagnostic}");
+/// }
+/// for diagnostic in book.fix(&repo, &FixOptions::default())? {
+///     eprintln!("{diagnostic}");
+/// }
+/// book.save()?;
+/// # Ok::<(), ajimi::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct Book {
+    files: Vec<BookFile>,
+}
+impl Book {
+    pub fn load<P: AsRef<Path>>(paths: impl IntoIterator<Item = P>) -> Result<Self> {
+        let files = paths
+            .into_iter()
+            .map(|path| BookFile::load(path.as_ref()))
+            .collect::<Result<_>>()?;
+        Ok(Self { files })
+    }
+    pub fn files(&self) -> &[BookFile] {
+        &self.files
+    }
+    pub fn files_mut(&mut self) -> &mut [BookFile] {
+        &mut self.files
+    }
+    /// Regenerates the code for all `ajimi::code` markers in memory. Call
+    /// [`Book::save`] to write the results. Returns what was remapped or
+    /// inserted, and the markers that could not be fixed.
+    pub fn fix<T: CommitResolver>(
+        &mut self,
+        repo: &T,
+        options: &FixOptions,
+    ) -> Result<Vec<Diagnostic>> {
+        let mut diagnostics = Vec::new();
+        // before inserting markers, so that remapped commits are not missing.
+        for file in &mut self.files {
+            let name = file.name();
+            diagnostics.extend(remap_change_ids(&name, &mut file.doc, &options.remap));
+        }
+        if options.insert_missing {
+            diagnostics.extend(insert_missing_markers(self, repo, &options.skip)?);
+        }
+        for file in &mut self.files {
+            let name = file.name();
+            diagnostics.extend(fix_document(repo, &name, &mut file.doc, options)?);
+        }
+        Ok(diagnostics)
+    }
+    /// Checks the book against the commits in `repo`.
+    pub fn check<T: CommitResolver>(
+        &self,
+        repo: &T,
+        options: &CheckOptions,
+    ) -> Result<Vec<Diagnostic>> {
+        check_book(self, repo, options)
+    }
+    /// Writes the changed files.
+    pub fn save(&mut self) -> Result<()> {
+        for file in &mut self.files {
+            file.save()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn diff_shows_changes_in_memory() {
+        let dir = std::env::temp_dir().join(format!("ajimi-book-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("book.md");
+        std::fs::write(&path, "a\nb\nc\n").unwrap();
+        let mut file = BookFile::load(&path).unwrap();
+        assert!(!file.is_changed());
+        *file.document_mut() = Document::parse("a\nB\nc\n");
+        assert!(file.is_changed());
+        let name = file.name();
+        assert_eq!(
+            file.diff(),
+            format!(
+                "--- a/{name}
++++ b/{name}
+@@ -1,3 +1,3 @@
+ a
+-b
++B
+ c
+"
+            )
+        );
+        file.save().unwrap();
+        assert!(!file.is_changed());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "a\nB\nc\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
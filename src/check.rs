@@ -1,15 +1,19 @@
+use crate::book::Book;
+use crate::book::BookFile;
+use crate::config::Config;
+use crate::document::Region;
+use crate::markdown::Outline;
+use crate::remap::suggest;
+use crate::repo::CommitMetadata;
 use crate::repo::CommitResolver;
 use crate::repo::GitRepo;
-use anyhow::anyhow;
-use anyhow::Result;
+use crate::skip::SkipRules;
+use crate::Error;
+use crate::Result;
 use argh::FromArgs;
-use regex::Regex;
 use std::collections::HashMap;
 use std::collections::HashSet;
-use std::fs;
-use std::fs::File;
-use std::io::BufRead;
-use std::io::BufReader;
+use std::fmt;
 use std::path::Path;
 use std::path::PathBuf;
 
@@ -20,214 +24,523 @@ pub struct Args {
     /// git repo for commits
     #[argh(option)]
     code: PathBuf,
+    /// config file (default: ajimi.toml if it exists)
+    #[argh(option)]
+    config: Option<PathBuf>,
+    /// print which skip rule exempted each commit
+    #[argh(switch, short = 'v')]
+    verbose: bool,
     /// files to fix
     #[argh(positional)]
     files: Vec<String>,
 }
 impl Args {
-    fn extract_codeblock_start_markers(
-        paths: &Vec<String>,
-    ) -> Result<Vec<(String, usize, String)>> {
-        let mut results = Vec::new();
-
-        for path_str in paths {
-            let path = Path::new(&path_str);
-            let file =
-                File::open(path).map_err(|_| anyhow!("Failed to open file: {}", path_str))?;
-            let reader = BufReader::new(file);
-            let mut code_block_stack = Vec::new();
-
-            for (line_number, line_result) in reader.lines().enumerate() {
-                let line = line_result.map_err(|_| {
-                    anyhow!(
-                        "Failed to read line {} in file: {}",
-                        line_number + 1,
-                        path_str
-                    )
-                })?;
-
-                if line.starts_with("```") {
-                    if code_block_stack.is_empty() {
-                        code_block_stack.push(line_number + 1);
-                        results.push((path_str.clone(), line_number + 1, line));
-                    } else {
-                        code_block_stack.pop();
-                    }
-                }
-            }
-
-            if !code_block_stack.is_empty() {
-                return Err(anyhow!(
-                    "Unclosed code block in file: {}, started at line: {}",
-                    path_str,
-                    code_block_stack[0]
-                ));
-            }
-        }
-
-        Ok(results)
-    }
-    fn verify_codeblock_start_markers(&self) -> Result<()> {
-        let codeblock_start_markers = Self::extract_codeblock_start_markers(&self.files)?;
-        let mut prev_file_name = None;
-        let mut is_fix_needed = false;
-        for (file, line_num, line) in codeblock_start_markers {
-            let lang = line.strip_prefix("```").unwrap_or_default();
-            let is_first_codeblock = if let Some(prev_file_name) = prev_file_name {
-                prev_file_name != file
-            } else {
-                true
-            };
-            prev_file_name = Some(file.clone());
-            match lang {
-                "rust,noplayground" | "rust" | "bash" | "txt" | "toml" | "bash_script_file"
-                | "gitconfig" => continue,
-                "" => {
-                    if is_first_codeblock {
-                        continue;
-                    } else {
-                        is_fix_needed = true;
-                        println!("{file}:{line_num}: {line}")
-                    }
+    pub fn run(&self) -> Result<()> {
+        let repo = GitRepo::new(self.code.clone());
+        let options = CheckOptions {
+            skip: Config::find(self.config.as_deref())?.skip,
+        };
+        eprintln!("checking {} files...", self.files.len());
+        let book = Book::load(&self.files)?;
+        let change_ids_in_book: HashSet<&str> = book
+            .files()
+            .iter()
+            .flat_map(|f| f.document().regions())
+            .filter_map(|r| r.change_id())
+            .collect();
+        println!(
+            "Total: {} ajimi change_ids found in the book.",
+            book.files()
+                .iter()
+                .flat_map(|f| f.document().regions())
+                .filter(|r| r.change_id().is_some())
+                .count()
+        );
+        let commits = repo.all_commit_summary_in_tree()?;
+        println!(
+            "Total: {} ajimi change_ids found in the repo.",
+            commits.len()
+        );
+        if self.verbose {
+            for c in commits.iter().rev() {
+                if change_ids_in_book.contains(c.change_id.as_str()) {
+                    continue;
                 }
-                _ => {
-                    is_fix_needed = true;
-                    println!("Unknown block lang: {file}:{line_num}: {line}")
+                if let Some(reason) = options.skip.reason(c)? {
+                    println!("skipped {} ({reason}): {}", c.change_id, c.title);
                 }
             }
         }
-        if is_fix_needed {
-            Err(anyhow!("Found some issues. Please fix them and try again!"))
-        } else {
+        let diagnostics = book.check(&repo, &options)?;
+        for d in &diagnostics {
+            println!("{d}");
+        }
+        if diagnostics.is_empty() {
             println!("PASS. It tastes good!");
             Ok(())
+        } else {
+            Err(Error::ChecksFailed)
         }
     }
-    fn verify_generated_code(&self) -> Result<()> {
-        let mut is_fix_needed = false;
+}
 
-        let mut id_to_book_path: HashMap<String, String> = HashMap::new();
-        let mut change_ids_in_book = Vec::new();
-        eprintln!("checking {} files...", self.files.len());
-        for file in &self.files {
-            let lines = fs::read_to_string(file)?;
-            let mut lines: Vec<String> = lines
-                .split("\n")
-                .filter(|s| s.contains("ajimi::code change_id"))
-                .map(|s| {
-                    s.split(" ")
-                        .skip_while(|s| s != &"change_id")
-                        .skip(1)
-                        .next()
-                        .unwrap_or("invalid")
-                        .to_string()
+/// Options for [`Book::check`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CheckOptions {
+    /// commits that need not be in the book
+    pub skip: SkipRules,
+}
+
+/// What a [`Diagnostic`] is about.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiagnosticKind {
+    /// An `ajimi::` comment that could not be parsed.
+    InvalidDirective,
+    /// Generated code that was edited by hand.
+    EditedRegion,
+    /// A change that appears before a change made earlier in the repo.
+    OrderGoesBack,
+    /// A change_id in the book that is not in the repo.
+    UnknownChangeId,
+    /// A change in the repo that is not in the book.
+    MissingChange,
+    /// An `ajimi::meta::title` that differs from the title of the commit.
+    StaleTitle,
+    /// Output of an `ajimi::run` command captured at another commit or for
+    /// another command, or not captured yet.
+    StaleOutput,
+    UnclosedCodeBlock,
+    /// A code block with an unknown or missing language.
+    CodeBlockLang,
+    /// An image without alt text or a source comment right before it.
+    ImageSource,
+    /// An `ajimi::code commit` marker whose commit could not be resolved to a
+    /// Change-Id; reported by `fix`.
+    UnknownCommit,
+    /// A marker whose change_id `fix` replaced with one from the remap file.
+    Remapped,
+    /// A marker and a draft paragraph inserted by `fix` for a missing change.
+    InsertedMarker,
+    /// An `ajimi::run` command that failed or could not be run by `fix`.
+    CommandFailed,
+}
+impl DiagnosticKind {
+    /// False for the changes `fix` reports, true for problems left in the
+    /// book.
+    pub fn is_error(&self) -> bool {
+        !matches!(
+            self,
+            DiagnosticKind::Remapped | DiagnosticKind::InsertedMarker
+        )
+    }
+}
+
+/// An issue found by [`Book::check`], or a change or problem reported by
+/// [`Book::fix`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub kind: DiagnosticKind,
+    pub file: Option<PathBuf>,
+    /// 1-based
+    pub line: Option<usize>,
+    pub change_id: Option<String>,
+    pub message: String,
+}
+impl Diagnostic {
+    pub(crate) fn new(kind: DiagnosticKind, message: String) -> Self {
+        Self {
+            kind,
+            file: None,
+            line: None,
+            change_id: None,
+            message,
+        }
+    }
+    pub(crate) fn in_file(mut self, file: &str, line: Option<usize>) -> Self {
+        self.file = Some(PathBuf::from(file));
+        self.line = line;
+        self
+    }
+    fn at(kind: DiagnosticKind, file: &BookFile, line: usize, message: String) -> Self {
+        Self {
+            kind,
+            file: Some(file.path().to_path_buf()),
+            line: Some(line),
+            change_id: None,
+            message,
+        }
+    }
+    pub(crate) fn with_change_id(mut self, change_id: &str) -> Self {
+        self.change_id = Some(change_id.to_string());
+        self
+    }
+}
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.file, self.line) {
+            (Some(file), Some(line)) => write!(f, "{}:{line}: {}", file.display(), self.message),
+            (Some(file), None) => write!(f, "{}: {}", file.display(), self.message),
+            _ => write!(f, "{}", self.message),
+        }
+    }
+}
+
+pub(crate) fn check_book<T: CommitResolver>(
+    book: &Book,
+    repo: &T,
+    options: &CheckOptions,
+) -> Result<Vec<Diagnostic>> {
+    let mut diagnostics = Vec::new();
+    diagnostics.extend(verify_directives(book));
+    diagnostics.extend(verify_generated_regions(book));
+    let commits = repo.all_commit_summary_in_tree()?;
+    diagnostics.extend(verify_generated_code(book, repo, &commits, &options.skip)?);
+    diagnostics.extend(verify_meta_titles(book, &commits));
+    diagnostics.extend(verify_run_outputs(book, &commits));
+    diagnostics.extend(verify_codeblock_start_markers(book));
+    diagnostics.extend(verify_image_source_comments(book));
+    Ok(diagnostics)
+}
+
+fn verify_directives(book: &Book) -> Vec<Diagnostic> {
+    book.files()
+        .iter()
+        .flat_map(|file| {
+            file.document().errors().iter().map(|e| {
+                Diagnostic::at(
+                    DiagnosticKind::InvalidDirective,
+                    file,
+                    e.line,
+                    format!("column {}: {}", e.column, e.message),
+                )
+            })
+        })
+        .collect()
+}
+
+fn verify_generated_regions(book: &Book) -> Vec<Diagnostic> {
+    book.files()
+        .iter()
+        .flat_map(|file| {
+            file.document()
+                .all_regions()
+                .filter(|r| r.is_edited())
+                .map(|region| {
+                    let change_id = region.change_id().unwrap_or_default();
+                    Diagnostic::at(
+                        DiagnosticKind::EditedRegion,
+                        file,
+                        region.marker.line,
+                        format!("generated code for change_id {change_id} was edited manually"),
+                    )
+                    .with_change_id(change_id)
                 })
-                .collect();
-            id_to_book_path.extend(
-                lines
-                    .iter()
-                    .map(|id| (id.to_string(), file.to_string()))
-                    .collect::<Vec<(String, String)>>(),
-            );
-            change_ids_in_book.extend(lines.drain(..));
+        })
+        .collect()
+}
+
+/// An `ajimi::code` marker with a change_id, in book order.
+pub(crate) struct Marker<'a> {
+    pub change_id: &'a str,
+    /// title of the commit in the repo, if any
+    pub title: Option<&'a str>,
+    /// index in [`Book::files`]
+    pub file: usize,
+    pub path: &'a Path,
+    pub line: usize,
+    /// position in the history, oldest first. `None` if not in the repo.
+    pub order: Option<usize>,
+    pub region: &'a Region,
+}
+impl fmt::Display for Marker<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.change_id)?;
+        if let Some(title) = self.title {
+            write!(f, " {title:?}")?;
         }
-        println!(
-            "Total: {} ajimi change_ids found in the book.",
-            change_ids_in_book.len()
-        );
-        let repo = GitRepo::new(self.code.clone());
-        let change_ids_in_repo = repo.all_commit_summary_in_tree()?;
-        println!(
-            "Total: {} ajimi change_ids found in the repo.",
-            change_ids_in_repo.len()
-        );
-        let mut repo_order_map = HashMap::new();
-        for (i, e) in change_ids_in_repo.iter().rev().enumerate() {
-            repo_order_map.insert(&e.change_id, (i, e));
+        write!(f, " ({}:{})", self.path.display(), self.line)
+    }
+}
+
+/// Markers in `book`, ordered against `commits` (newest first, as returned by
+/// [`CommitResolver::all_commit_summary_in_tree`]).
+pub(crate) fn book_markers<'a>(book: &'a Book, commits: &'a [CommitMetadata]) -> Vec<Marker<'a>> {
+    let order: HashMap<&str, (usize, &str)> = commits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, c)| (c.change_id.as_str(), (i, c.title.as_str())))
+        .collect();
+    let mut markers = Vec::new();
+    for (i, file) in book.files().iter().enumerate() {
+        for region in file.document().regions() {
+            let Some(change_id) = region.change_id() else {
+                continue;
+            };
+            let known = order.get(change_id);
+            markers.push(Marker {
+                change_id,
+                title: known.map(|(_, title)| *title),
+                file: i,
+                path: file.path(),
+                line: region.marker.line,
+                order: known.map(|(order, _)| *order),
+                region,
+            });
         }
-        let mut next_expected_order = 0;
-        let mut found_ids: HashSet<String> = HashSet::new();
-        for id_in_book in change_ids_in_book {
-            let book_path = id_to_book_path
-                .get(&id_in_book)
-                .map(|s| s.as_str())
-                .unwrap_or("?");
-            if let Some((order, _)) = repo_order_map.get(&id_in_book) {
-                if *order < next_expected_order {
-                    println!("{id_in_book} @ {book_path}: order should not go back");
-                    is_fix_needed = true;
-                } else {
-                    next_expected_order = *order + 1;
+    }
+    markers
+}
+
+/// The markers of the closest commits made before and after the commit at
+/// `order` in the history.
+pub(crate) fn neighbours<'m, 'a>(
+    markers: &'m [Marker<'a>],
+    order: usize,
+) -> (Option<&'m Marker<'a>>, Option<&'m Marker<'a>>) {
+    let prev = markers
+        .iter()
+        .filter(|m| m.order.is_some_and(|o| o < order))
+        .fold(None, |best: Option<&Marker>, m| match best {
+            Some(b) if b.order >= m.order => Some(b),
+            _ => Some(m),
+        });
+    let next = markers
+        .iter()
+        .filter(|m| m.order.is_some_and(|o| o > order))
+        .fold(None, |best: Option<&Marker>, m| match best {
+            Some(b) if b.order <= m.order => Some(b),
+            _ => Some(m),
+        });
+    (prev, next)
+}
+
+pub(crate) fn describe_place(prev: Option<&Marker>, next: Option<&Marker>) -> Option<String> {
+    match (prev, next) {
+        (Some(p), Some(n)) => Some(format!("after {p} and before {n}")),
+        (Some(p), None) => Some(format!("after {p}")),
+        (None, Some(n)) => Some(format!("before {n}")),
+        (None, None) => None,
+    }
+}
+
+fn verify_generated_code<T: CommitResolver>(
+    book: &Book,
+    repo: &T,
+    change_ids_in_repo: &[CommitMetadata],
+    skip: &SkipRules,
+) -> Result<Vec<Diagnostic>> {
+    let mut diagnostics = Vec::new();
+    let markers = book_markers(book, change_ids_in_repo);
+    let in_book: HashSet<&str> = markers.iter().map(|m| m.change_id).collect();
+    // candidates for change_ids that are not in the repo any more
+    let not_in_book: Vec<&CommitMetadata> = change_ids_in_repo
+        .iter()
+        .filter(|c| !in_book.contains(c.change_id.as_str()))
+        .collect();
+    let mut next_expected_order = 0;
+    let mut found_ids: HashSet<&str> = HashSet::new();
+    for marker in &markers {
+        let file = &book.files()[marker.file];
+        let id_in_book = marker.change_id;
+        if let Some(order) = marker.order {
+            if order < next_expected_order {
+                let mut message = format!("{id_in_book}: order should not go back");
+                let (prev, next) = neighbours(&markers, order);
+                if let Some(place) = describe_place(prev, next) {
+                    message += &format!("\n  it belongs {place}");
                 }
-                found_ids.insert(id_in_book);
+                diagnostics.push(
+                    Diagnostic::at(DiagnosticKind::OrderGoesBack, file, marker.line, message)
+                        .with_change_id(id_in_book),
+                );
             } else {
-                println!("{id_in_book} @ {book_path}: change_id not found in the code");
-                is_fix_needed = true;
+                next_expected_order = order + 1;
             }
-        }
-        for e in change_ids_in_repo.iter().rev() {
-            if !found_ids.contains(&e.change_id) && !e.title.contains("SKIP_EXPLAIN: ") {
-                println!(
-                    "change in code but book: <!-- ajimi::code change_id {} -->",
-                    e.change_id
+            found_ids.insert(id_in_book);
+        } else {
+            let mut message = format!("{id_in_book}: change_id not found in the code");
+            if let Some((title, _)) = marker.region.meta("title") {
+                message += &format!("\n  last known title: {title:?}");
+            }
+            for candidate in suggest(repo, marker.region, &not_in_book) {
+                let c = candidate.commit;
+                message += &format!(
+                    "\n  maybe {} {:?} ({:.0}% similar)",
+                    c.change_id,
+                    c.title,
+                    candidate.score * 100.0
                 );
-                println!("  {}", e.title);
-                is_fix_needed = true;
             }
+            diagnostics.push(
+                Diagnostic::at(DiagnosticKind::UnknownChangeId, file, marker.line, message)
+                    .with_change_id(id_in_book),
+            );
         }
-        if is_fix_needed {
-            Err(anyhow!("Found some issues. Please fix them and try again!"))
-        } else {
-            println!("PASS. It tastes good!");
-            Ok(())
+    }
+    for (order, e) in change_ids_in_repo.iter().rev().enumerate() {
+        if !found_ids.contains(e.change_id.as_str()) && skip.reason(e)?.is_none() {
+            let mut message = format!(
+                "change in code but book: <!-- ajimi::code change_id {} -->\n  {}",
+                e.change_id, e.title
+            );
+            let (prev, next) = neighbours(&markers, order);
+            if let Some(place) = describe_place(prev, next) {
+                message += &format!("\n  insert it {place}");
+            }
+            diagnostics.push(Diagnostic {
+                kind: DiagnosticKind::MissingChange,
+                file: None,
+                line: None,
+                change_id: Some(e.change_id.clone()),
+                message,
+            });
         }
     }
-    fn extract_image_source_comments(
-        paths: &Vec<String>,
-    ) -> Result<Vec<(String, usize, Option<String>, String)>> {
-        let mut results = Vec::new();
-        let re = Regex::new(r"!\[(.*?)\]\((.*?)\)").unwrap();
-
-        for path_str in paths {
-            let path = Path::new(&path_str);
-            let content = fs::read_to_string(path)
-                .map_err(|_| anyhow!("Failed to open file: {}", path_str))?;
-            let lines: Vec<&str> = content.lines().collect();
+    Ok(diagnostics)
+}
 
-            for i in 1..lines.len() {
-                if !re.is_match(lines[i]) {
-                    continue;
-                }
-                let tag = if lines[i - 1].starts_with("<!-- ") {
-                    Some(lines[i - 1].to_string())
-                } else {
-                    None
-                };
-                results.push((path_str.to_string(), i - 1, tag, lines[i].to_string()))
+/// Reports `ajimi::meta::title` lines left behind by a reworded commit.
+fn verify_meta_titles(book: &Book, commits: &[CommitMetadata]) -> Vec<Diagnostic> {
+    let titles: HashMap<&str, &str> = commits
+        .iter()
+        .map(|c| (c.change_id.as_str(), c.title.as_str()))
+        .collect();
+    let mut diagnostics = Vec::new();
+    for file in book.files() {
+        for region in file.document().regions() {
+            let Some(change_id) = region.change_id() else {
+                continue;
+            };
+            let (Some((title, line)), Some(current)) =
+                (region.meta("title"), titles.get(change_id))
+            else {
+                continue;
+            };
+            if title != *current {
+                diagnostics.push(
+                    Diagnostic::at(
+                        DiagnosticKind::StaleTitle,
+                        file,
+                        line,
+                        format!("{change_id}: title {title:?} is now {current:?} in the repo; run fix to update it"),
+                    )
+                    .with_change_id(change_id),
+                );
             }
         }
-        Ok(results)
-    }
-    fn verify_image_source_comments(&self) -> Result<()> {
-        let mut is_fix_needed = false;
-        let markers = Self::extract_image_source_comments(&self.files)?;
-        for (file, line_num, line, imgline) in markers {
-            if line.is_none() || imgline.contains("![]") {
-                println!("{file}:{line_num}: {line:?}: {imgline}");
-                is_fix_needed = true;
-            }
+    }
+    diagnostics
+}
+
+/// Reports `ajimi::run` output that `fix` would regenerate. Commands are not
+/// run; the output is stale if the commit or the command changed.
+fn verify_run_outputs(book: &Book, commits: &[CommitMetadata]) -> Vec<Diagnostic> {
+    let hashes: HashMap<&str, &str> = commits
+        .iter()
+        .map(|c| (c.change_id.as_str(), c.hash.as_str()))
+        .collect();
+    let mut diagnostics = Vec::new();
+    for file in book.files() {
+        for region in file.document().runs() {
+            let Some(change_id) = region.change_id() else {
+                continue;
+            };
+            let cmd = region
+                .directive
+                .attrs
+                .get("cmd")
+                .cloned()
+                .unwrap_or_default();
+            let meta = |key| region.meta(key).map(|(value, _)| value);
+            let (kind, message) = match (hashes.get(change_id), meta("commit"), meta("cmd")) {
+                (None, _, _) => (
+                    DiagnosticKind::UnknownChangeId,
+                    format!("{change_id}: change_id not found in the code"),
+                ),
+                (Some(_), None, _) => (
+                    DiagnosticKind::StaleOutput,
+                    format!("{change_id}: output of {cmd:?} is not generated yet; run fix"),
+                ),
+                (Some(hash), Some(captured), _) if captured != *hash => (
+                    DiagnosticKind::StaleOutput,
+                    format!(
+                        "{change_id}: output of {cmd:?} was captured at {captured:.12}, but the commit is now {hash:.12}; run fix"
+                    ),
+                ),
+                (Some(_), Some(_), captured) if captured.as_ref() != Some(&cmd) => (
+                    DiagnosticKind::StaleOutput,
+                    format!(
+                        "{change_id}: output was captured for {:?}, not {cmd:?}; run fix",
+                        captured.unwrap_or_default()
+                    ),
+                ),
+                _ => continue,
+            };
+            diagnostics.push(
+                Diagnostic::at(kind, file, region.marker.line, message).with_change_id(change_id),
+            );
         }
-        if is_fix_needed {
-            Err(anyhow!("Found some issues. Please fix them and try again!"))
-        } else {
-            println!("PASS. It tastes good!");
-            Ok(())
+    }
+    diagnostics
+}
+
+fn verify_codeblock_start_markers(book: &Book) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for file in book.files() {
+        let outline = Outline::parse(&file.document().to_string());
+        for (i, fence) in outline.fences.iter().enumerate() {
+            if !fence.is_closed {
+                diagnostics.push(Diagnostic::at(
+                    DiagnosticKind::UnclosedCodeBlock,
+                    file,
+                    fence.line,
+                    "Unclosed code block".to_string(),
+                ));
+                continue;
+            }
+            match fence.info.as_str() {
+                "rust,noplayground" | "rust" | "bash" | "txt" | "toml" | "bash_script_file"
+                | "gitconfig" => {}
+                // the first code block of a file may omit the language
+                "" if i == 0 => {}
+                "" => diagnostics.push(Diagnostic::at(
+                    DiagnosticKind::CodeBlockLang,
+                    file,
+                    fence.line,
+                    fence.opening.clone(),
+                )),
+                _ => diagnostics.push(Diagnostic::at(
+                    DiagnosticKind::CodeBlockLang,
+                    file,
+                    fence.line,
+                    format!("Unknown block lang: {}", fence.opening),
+                )),
+            }
         }
     }
-    pub fn run(&self) -> Result<()> {
-        self.verify_generated_code()?;
-        self.verify_codeblock_start_markers()?;
-        self.verify_image_source_comments()?;
-        Ok(())
+    diagnostics
+}
+
+fn verify_image_source_comments(book: &Book) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for file in book.files() {
+        let outline = Outline::parse(&file.document().to_string());
+        for image in &outline.images {
+            let tag = outline
+                .comments
+                .iter()
+                .find(|c| c.line + 1 == image.line)
+                .map(|c| c.text.clone());
+            if tag.is_none() || !image.has_alt {
+                diagnostics.push(Diagnostic::at(
+                    DiagnosticKind::ImageSource,
+                    file,
+                    image.line,
+                    format!("{tag:?}: {}", image.source_line),
+                ));
+            }
+        }
     }
+    diagnostics
 }
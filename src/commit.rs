@@ -0,0 +1,363 @@
+use crate::Error;
+use crate::Result;
+
+/// A commit with its diff, as returned by
+/// [`CommitResolver::commit_from_change_id`](crate::CommitResolver::commit_from_change_id).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Commit {
+    pub hash: String,
+    pub title: String,
+    /// commit message without the title and the trailers
+    pub body: String,
+    /// `Key: value` lines at the end of the commit message, in order
+    pub trailers: Vec<(String, String)>,
+    pub files: Vec<FileDiff>,
+}
+impl Commit {
+    /// Builds a commit from a full commit message (`%B`) and a diff as printed
+    /// by `git diff`.
+    pub fn parse(hash: &str, message: &str, diff: &str) -> Result<Self> {
+        let (title, body, trailers) = parse_message(message);
+        Ok(Self {
+            hash: hash.to_string(),
+            title,
+            body,
+            trailers,
+            files: parse_diff(diff)?,
+        })
+    }
+    /// The value of the first trailer named `key`, e.g. `Change-Id`.
+    pub fn trailer(&self, key: &str) -> Option<&str> {
+        self.trailers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+    pub fn change_id(&self) -> Option<&str> {
+        self.trailer("Change-Id")
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileStatus {
+    Added,
+    Deleted,
+    Modified,
+    Renamed,
+    Copied,
+}
+
+/// Changes to a file in a commit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileDiff {
+    /// `None` if the file was added
+    pub old_path: Option<String>,
+    /// `None` if the file was deleted
+    pub new_path: Option<String>,
+    pub status: FileStatus,
+    pub hunks: Vec<Hunk>,
+}
+impl FileDiff {
+    /// The path after the change, or before it for deleted files.
+    pub fn path(&self) -> &str {
+        self.new_path
+            .as_deref()
+            .or(self.old_path.as_deref())
+            .unwrap_or_default()
+    }
+}
+
+/// A `@@ -old_start,old_lines +new_start,new_lines @@ context` section.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Hunk {
+    /// 1-based
+    pub old_start: usize,
+    pub old_lines: usize,
+    /// 1-based
+    pub new_start: usize,
+    pub new_lines: usize,
+    /// the section heading after the second `@@`, e.g. the enclosing function
+    pub context: String,
+    pub lines: Vec<Line>,
+}
+
+/// A line in a hunk, without the leading ` `, `+` or `-`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Line {
+    Context(String),
+    Added(String),
+    Removed(String),
+}
+impl Line {
+    pub fn text(&self) -> &str {
+        match self {
+            Line::Context(s) | Line::Added(s) | Line::Removed(s) => s,
+        }
+    }
+}
+
+/// Splits a commit message into the title, the body and the trailers.
+pub(crate) fn parse_message(message: &str) -> (String, String, Vec<(String, String)>) {
+    let message = message.trim();
+    let (title, rest) = message.split_once('\n').unwrap_or((message, ""));
+    let rest = rest.trim();
+    let (body, last) = match rest.rsplit_once("\n\n") {
+        Some((body, last)) => (body.trim_end(), last),
+        None => ("", rest),
+    };
+    let trailers: Option<Vec<(String, String)>> = last
+        .lines()
+        .map(|line| {
+            let (key, value) = line.split_once(':')?;
+            let is_token =
+                !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+            is_token.then(|| (key.to_string(), value.trim().to_string()))
+        })
+        .collect();
+    match trailers {
+        Some(trailers) if !last.is_empty() => (title.to_string(), body.to_string(), trailers),
+        _ => (title.to_string(), rest.to_string(), Vec::new()),
+    }
+}
+
+fn invalid(message: String) -> Error {
+    Error::InvalidPatch { message }
+}
+
+/// Parses `start[,count]` of a hunk header.
+fn parse_range(s: &str, header: &str) -> Result<(usize, usize)> {
+    let (start, count) = s.split_once(',').unwrap_or((s, "1"));
+    match (start.parse(), count.parse()) {
+        (Ok(start), Ok(count)) => Ok((start, count)),
+        _ => Err(invalid(format!("Invalid hunk header: {header}"))),
+    }
+}
+
+fn parse_hunk_header(header: &str) -> Result<Hunk> {
+    let invalid_header = || invalid(format!("Invalid hunk header: {header}"));
+    let (ranges, context) = header
+        .strip_prefix("@@ ")
+        .and_then(|s| s.split_once(" @@"))
+        .ok_or_else(invalid_header)?;
+    let (old, new) = ranges.split_once(' ').ok_or_else(invalid_header)?;
+    let (old_start, old_lines) =
+        parse_range(old.strip_prefix('-').ok_or_else(invalid_header)?, header)?;
+    let (new_start, new_lines) =
+        parse_range(new.strip_prefix('+').ok_or_else(invalid_header)?, header)?;
+    Ok(Hunk {
+        old_start,
+        old_lines,
+        new_start,
+        new_lines,
+        context: context.trim().to_string(),
+        lines: Vec::new(),
+    })
+}
+
+/// Paths in `diff --git a/<old> b/<new>`. Used until `---`, `+++` or
+/// `rename` lines tell the paths unambiguously.
+fn parse_diff_git_header(header: &str) -> Result<(String, String)> {
+    let invalid_header = || invalid(format!("Invalid diff header: {header}"));
+    let rest = header
+        .strip_prefix("diff --git ")
+        .ok_or_else(invalid_header)?;
+    // "a/x b/x" can be split in the middle even if x contains " b/".
+    let mid = rest.len() / 2;
+    if rest.is_char_boundary(mid) && rest[mid..].starts_with(' ') {
+        if let (Some(old), Some(new)) = (
+            rest[..mid].strip_prefix("a/"),
+            rest[mid + 1..].strip_prefix("b/"),
+        ) {
+            if old == new {
+                return Ok((old.to_string(), new.to_string()));
+            }
+        }
+    }
+    let (old, new) = rest.split_once(" b/").ok_or_else(invalid_header)?;
+    let old = old.strip_prefix("a/").ok_or_else(invalid_header)?;
+    Ok((old.to_string(), new.to_string()))
+}
+
+/// Parses the output of `git diff` (or `git log -p` without the commit
+/// headers) into files and hunks.
+pub fn parse_diff(text: &str) -> Result<Vec<FileDiff>> {
+    let mut files: Vec<FileDiff> = Vec::new();
+    // lines left in the current hunk, (old, new)
+    let mut remaining = (0, 0);
+    let text = text.strip_suffix('\n').unwrap_or(text);
+    for line in text.split('\n') {
+        if remaining != (0, 0) {
+            let hunk = files
+                .last_mut()
+                .and_then(|f| f.hunks.last_mut())
+                .ok_or_else(|| invalid(format!("Line out of hunk: {line:?}")))?;
+            let mut chars = line.chars();
+            let kind = chars.next();
+            let text = chars.as_str();
+            let line = match kind {
+                // some tools strip the trailing space of empty context lines
+                Some(' ') | None if remaining.0 > 0 && remaining.1 > 0 => {
+                    remaining = (remaining.0 - 1, remaining.1 - 1);
+                    Line::Context(text.to_string())
+                }
+                Some('-') if remaining.0 > 0 => {
+                    remaining.0 -= 1;
+                    Line::Removed(text.to_string())
+                }
+                Some('+') if remaining.1 > 0 => {
+                    remaining.1 -= 1;
+                    Line::Added(text.to_string())
+                }
+                // "\ No newline at end of file"
+                Some('\\') => continue,
+                _ => return Err(invalid(format!("Unexpected line in a hunk: {line:?}"))),
+            };
+            hunk.lines.push(line);
+            continue;
+        }
+        if line.starts_with("diff --git ") {
+            let (old, new) = parse_diff_git_header(line)?;
+            files.push(FileDiff {
+                old_path: Some(old),
+                new_path: Some(new),
+                status: FileStatus::Modified,
+                hunks: Vec::new(),
+            });
+            continue;
+        }
+        let Some(file) = files.last_mut() else {
+            if line.trim().is_empty() {
+                continue;
+            }
+            return Err(invalid(format!("Invalid part found: {line:?}")));
+        };
+        if line.starts_with("@@ ") {
+            let hunk = parse_hunk_header(line)?;
+            remaining = (hunk.old_lines, hunk.new_lines);
+            file.hunks.push(hunk);
+        } else if line.starts_with("new file mode") {
+            file.status = FileStatus::Added;
+            file.old_path = None;
+        } else if line.starts_with("deleted file mode") {
+            file.status = FileStatus::Deleted;
+            file.new_path = None;
+        } else if let Some(path) = line.strip_prefix("rename from ") {
+            file.status = FileStatus::Renamed;
+            file.old_path = Some(path.to_string());
+        } else if let Some(path) = line.strip_prefix("rename to ") {
+            file.new_path = Some(path.to_string());
+        } else if let Some(path) = line.strip_prefix("copy from ") {
+            file.status = FileStatus::Copied;
+            file.old_path = Some(path.to_string());
+        } else if let Some(path) = line.strip_prefix("copy to ") {
+            file.new_path = Some(path.to_string());
+        } else if let Some(path) = line.strip_prefix("--- ") {
+            file.old_path = path.strip_prefix("a/").map(|s| s.to_string());
+        } else if let Some(path) = line.strip_prefix("+++ ") {
+            file.new_path = path.strip_prefix("b/").map(|s| s.to_string());
+        }
+        // other extended headers ("index", "old mode", "similarity index",
+        // "Binary files ... differ") do not matter here.
+    }
+    if remaining != (0, 0) {
+        return Err(invalid(format!(
+            "Hunk ended early in {}",
+            files.last().map(|f| f.path()).unwrap_or_default()
+        )));
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_commit_message() {
+        let commit = Commit::parse(
+            "abc",
+            "Add loop\n\nThe body\n\nmore body\n\nChange-Id: I0\nSigned-off-by: A <a@example.com>\n",
+            "",
+        )
+        .unwrap();
+        assert_eq!(commit.title, "Add loop");
+        assert_eq!(commit.body, "The body\n\nmore body");
+        assert_eq!(commit.change_id(), Some("I0"));
+        assert_eq!(commit.trailer("signed-off-by"), Some("A <a@example.com>"));
+        let commit =
+            Commit::parse("abc", "Title\n\nNot: a trailer\nbecause of this\n", "").unwrap();
+        assert_eq!(commit.body, "Not: a trailer\nbecause of this");
+        assert!(commit.trailers.is_empty());
+        let commit = Commit::parse("abc", "Title only", "").unwrap();
+        assert_eq!(
+            (commit.title.as_str(), commit.body.as_str()),
+            ("Title only", "")
+        );
+    }
+
+    #[test]
+    fn parse_files_and_hunks() {
+        let files = parse_diff(
+            "diff --git a/src/main.rs b/src/main.rs
+index e7a11a9..2c7001e 100644
+--- a/src/main.rs
++++ b/src/main.rs
+@@ -1,3 +1,4 @@ fn main() {
+ fn main() {
+-    a();
++    b();
++    c();
+
+\\ No newline at end of file
+diff --git a/new file.txt b/new file.txt
+new file mode 100644
+index 0000000..e69de29
+--- /dev/null
++++ b/new file.txt
+@@ -0,0 +1 @@
++x
+diff --git a/old.rs b/new.rs
+similarity index 90%
+rename from old.rs
+rename to new.rs
+diff --git a/gone.rs b/gone.rs
+deleted file mode 100644
+",
+        )
+        .unwrap();
+        assert_eq!(files.len(), 4);
+        assert_eq!(files[0].status, FileStatus::Modified);
+        assert_eq!(files[0].path(), "src/main.rs");
+        assert_eq!(
+            files[0].hunks,
+            vec![Hunk {
+                old_start: 1,
+                old_lines: 3,
+                new_start: 1,
+                new_lines: 4,
+                context: "fn main() {".to_string(),
+                lines: vec![
+                    Line::Context("fn main() {".to_string()),
+                    Line::Removed("    a();".to_string()),
+                    Line::Added("    b();".to_string()),
+                    Line::Added("    c();".to_string()),
+                    Line::Context("".to_string()),
+                ],
+            }]
+        );
+        assert_eq!(files[1].status, FileStatus::Added);
+        assert_eq!(files[1].old_path, None);
+        assert_eq!(files[1].path(), "new file.txt");
+        assert_eq!(files[2].status, FileStatus::Renamed);
+        assert_eq!(files[2].old_path.as_deref(), Some("old.rs"));
+        assert_eq!(files[2].new_path.as_deref(), Some("new.rs"));
+        assert_eq!(files[3].status, FileStatus::Deleted);
+        assert_eq!(files[3].path(), "gone.rs");
+
+        assert!(parse_diff("").unwrap().is_empty());
+        assert!(parse_diff("aaa").is_err());
+        assert!(parse_diff("diff --git broken").is_err());
+        assert!(parse_diff("diff --git a/x b/x\n@@ -1 +1 @@\n?x\n").is_err());
+        assert!(parse_diff("diff --git a/x b/x\n@@ -1,2 +1,2 @@\n x\n").is_err());
+    }
+}
@@ -0,0 +1,83 @@
+use crate::fix::RunOptions;
+use crate::repo::DiffOptions;
+use crate::skip::SkipRules;
+use crate::verify::VerifyOptions;
+use crate::Error;
+use crate::Result;
+use serde::Deserialize;
+use std::path::Path;
+
+/// The file read when `--config` is not given, if it exists.
+pub const DEFAULT_CONFIG: &str = "ajimi.toml";
+
+/// Settings read from `ajimi.toml`.
+///
+/// ```toml
+/// [skip]
+/// title_contains = ["SKIP_EXPLAIN: "]
+/// paths = ["scripts/**"]
+/// authors = ["bot@example.com"]
+/// list = "skip.txt"
+///
+/// [verify]
+/// command = "cargo test"
+///
+/// [run]
+/// enabled = true
+/// cache_dir = ".ajimi-cache"
+///
+/// [diff]
+/// context = 5
+/// ```
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub skip: SkipRules,
+    pub verify: VerifyOptions,
+    pub run: RunOptions,
+    pub diff: DiffOptions,
+}
+impl Config {
+    /// Reads a config file. Relative paths in it are relative to the file.
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path).map_err(Error::io(path))?;
+        let mut config: Config = toml::from_str(&content).map_err(|e| Error::Config {
+            message: format!("{}: {e}", path.display()),
+        })?;
+        let base_dir = path.parent().unwrap_or(Path::new(""));
+        config.skip.load_list(base_dir)?;
+        if let Some(dir) = &mut config.run.cache_dir {
+            *dir = base_dir.join(&dir);
+        }
+        Ok(config)
+    }
+    /// Reads `path` if given, otherwise [`DEFAULT_CONFIG`] if it exists.
+    pub fn find(path: Option<&Path>) -> Result<Self> {
+        match path {
+            Some(path) => Self::load(path),
+            None if Path::new(DEFAULT_CONFIG).exists() => Self::load(Path::new(DEFAULT_CONFIG)),
+            None => Ok(Self::default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_config() {
+        let config: Config = toml::from_str(
+            "[skip]
+paths = [\"scripts/**\"]
+trailer = false
+",
+        )
+        .unwrap();
+        assert_eq!(config.skip.paths(), vec!["scripts/**"]);
+        assert!(!config.skip.trailer);
+        // defaults are kept for keys not given.
+        assert_eq!(config.skip.title_contains, vec!["SKIP_EXPLAIN: "]);
+        assert!(toml::from_str::<Config>("[skip]\nunknown = 1\n").is_err());
+    }
+}
@@ -0,0 +1,225 @@
+use crate::book::Book;
+use crate::check::book_markers;
+use crate::check::neighbours;
+use crate::commit::Commit;
+use crate::commit::Line;
+use crate::config::Config;
+use crate::repo::CommitMetadata;
+use crate::repo::CommitResolver;
+use crate::repo::GitRepo;
+use crate::skip::SkipRules;
+use crate::Error;
+use crate::Result;
+use argh::FromArgs;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Report how many commits each file explains
+#[argh(subcommand, name = "coverage")]
+pub struct Args {
+    /// git repo for commits
+    #[argh(option)]
+    code: PathBuf,
+    /// print the report as JSON
+    #[argh(switch)]
+    json: bool,
+    /// number of the largest unexplained commits to list (default: 10)
+    #[argh(option, default = "10")]
+    top: usize,
+    /// config file (default: ajimi.toml if it exists)
+    #[argh(option)]
+    config: Option<PathBuf>,
+    /// markdown files of the book
+    #[argh(positional)]
+    files: Vec<String>,
+}
+impl Args {
+    pub fn run(&self) -> Result<()> {
+        let repo = GitRepo::new(self.code.clone());
+        let skip = Config::find(self.config.as_deref())?.skip;
+        let book = Book::load(&self.files)?;
+        let report = coverage(&book, &repo, &skip, self.top)?;
+        if self.json {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&report).expect("a report is always serializable")
+            );
+        } else {
+            print!("{report}");
+        }
+        Ok(())
+    }
+}
+
+/// Commits explained, skipped and missing in a book file or in the whole book.
+/// Skipped and missing commits count for the file where they would be
+/// inserted in history order.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Counts {
+    pub explained: usize,
+    pub skipped: usize,
+    pub missing: usize,
+    /// lines added by the explained commits
+    pub added: usize,
+    /// lines removed by the explained commits
+    pub removed: usize,
+}
+impl Counts {
+    fn add(&mut self, other: &Counts) {
+        self.explained += other.explained;
+        self.skipped += other.skipped;
+        self.missing += other.missing;
+        self.added += other.added;
+        self.removed += other.removed;
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct FileCoverage {
+    pub path: PathBuf,
+    #[serde(flatten)]
+    pub counts: Counts,
+}
+
+/// A commit that is neither in the book nor skipped.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Unexplained {
+    pub change_id: String,
+    pub hash: String,
+    pub title: String,
+    pub added: usize,
+    pub removed: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Coverage {
+    pub files: Vec<FileCoverage>,
+    pub total: Counts,
+    /// the largest unexplained commits, by lines changed
+    pub unexplained: Vec<Unexplained>,
+}
+
+/// Lines (added, removed) in a commit.
+fn changed_lines(commit: &Commit) -> (usize, usize) {
+    let lines = commit
+        .files
+        .iter()
+        .flat_map(|f| &f.hunks)
+        .flat_map(|h| &h.lines);
+    lines.fold((0, 0), |(added, removed), line| match line {
+        Line::Added(_) => (added + 1, removed),
+        Line::Removed(_) => (added, removed + 1),
+        Line::Context(_) => (added, removed),
+    })
+}
+
+/// Computes the coverage of `book`, listing up to `top` unexplained commits.
+pub fn coverage<T: CommitResolver>(
+    book: &Book,
+    repo: &T,
+    skip: &SkipRules,
+    top: usize,
+) -> Result<Coverage> {
+    let commits = repo.all_commit_summary_in_tree()?;
+    let markers = book_markers(book, &commits);
+    let diffs = repo.all_commits_in_tree()?;
+    let lines: HashMap<&str, (usize, usize)> = diffs
+        .iter()
+        .map(|c| (c.hash.as_str(), changed_lines(c)))
+        .collect();
+    let changed = |c: &CommitMetadata| {
+        lines
+            .get(c.hash.as_str())
+            .copied()
+            .ok_or_else(|| Error::not_found(format!("diff of commit {}", c.hash)))
+    };
+    let mut files: Vec<FileCoverage> = book
+        .files()
+        .iter()
+        .map(|f| FileCoverage {
+            path: f.path().to_path_buf(),
+            counts: Counts::default(),
+        })
+        .collect();
+    let mut total = Counts::default();
+    let mut unexplained = Vec::new();
+    for (order, c) in commits.iter().rev().enumerate() {
+        let marker = markers.iter().find(|m| m.order == Some(order));
+        let file = marker.map(|m| m.file).or_else(|| {
+            let (prev, next) = neighbours(&markers, order);
+            prev.or(next).map(|m| m.file)
+        });
+        let mut counts = Counts::default();
+        if marker.is_some() {
+            let (added, removed) = changed(c)?;
+            counts.explained = 1;
+            counts.added = added;
+            counts.removed = removed;
+        } else if skip.reason(c)?.is_some() {
+            counts.skipped = 1;
+        } else {
+            let (added, removed) = changed(c)?;
+            counts.missing = 1;
+            unexplained.push(Unexplained {
+                change_id: c.change_id.clone(),
+                hash: c.hash.clone(),
+                title: c.title.clone(),
+                added,
+                removed,
+            });
+        }
+        total.add(&counts);
+        if let Some(file) = file {
+            files[file].counts.add(&counts);
+        }
+    }
+    // stable, so commits of the same size stay in history order.
+    unexplained.sort_by_key(|u| std::cmp::Reverse(u.added + u.removed));
+    unexplained.truncate(top);
+    Ok(Coverage {
+        files,
+        total,
+        unexplained,
+    })
+}
+
+impl fmt::Display for Coverage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<String> = self
+            .files
+            .iter()
+            .map(|c| c.path.display().to_string())
+            .collect();
+        let width = names.iter().map(|n| n.len()).max().unwrap_or(0).max(5);
+        writeln!(
+            f,
+            "{:width$} {:>9} {:>7} {:>7} {:>7} {:>7}",
+            "file", "explained", "skipped", "missing", "+lines", "-lines"
+        )?;
+        let row = |f: &mut fmt::Formatter<'_>, name: &str, c: &Counts| {
+            writeln!(
+                f,
+                "{name:width$} {:>9} {:>7} {:>7} {:>7} {:>7}",
+                c.explained, c.skipped, c.missing, c.added, c.removed
+            )
+        };
+        for (name, file) in names.iter().zip(&self.files) {
+            row(f, name, &file.counts)?;
+        }
+        row(f, "total", &self.total)?;
+        if !self.unexplained.is_empty() {
+            writeln!(f, "\nlargest unexplained commits:")?;
+            for u in &self.unexplained {
+                writeln!(
+                    f,
+                    "  {} +{} -{} {}",
+                    u.change_id, u.added, u.removed, u.title
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
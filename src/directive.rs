@@ -0,0 +1,467 @@
+//! Parser for the ajimi directives written in HTML comments.
+//!
+//! ```text
+//! directive := "<!--" ws* "ajimi::" kind (ws+ arg)* ws* "-->"
+//! kind      := name ("::" name)*
+//! arg       := key "=" value | value
+//! value     := bare | '"' (char | "\\" char)* '"'
+//! ```
+//!
+//! The target of `ajimi::code` and `ajimi::end` is written as a pair of
+//! words, e.g. `change_id I0123` (`change_id=I0123` is also accepted), and a
+//! bare word in other places is a flag, i.e. `flag` means `flag=true`.
+//! `--` in a quoted value is written `-\-`, as `-->` would end the comment.
+//!
+//! ```text
+//! <!-- ajimi::code change_id I0123 -->
+//! <!-- ajimi::meta::title "Add \"foo\"" -->
+//! <!-- ajimi::end change_id I0123 hash=0123456789abcdef -->
+//! <!-- ajimi::run change_id I0123 cmd="cargo run --quiet" -->
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+pub type Attrs = BTreeMap<String, String>;
+
+/// The commit an `ajimi::code` marker refers to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Target {
+    ChangeId(String),
+    /// A commit hash, which `fix` replaces with its Change-Id.
+    Commit(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CodeDirective {
+    pub target: Target,
+    pub attrs: Attrs,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Directive {
+    /// `ajimi::code`: generate the diff of a commit here.
+    Code(CodeDirective),
+    /// `ajimi::run`: run the command in the `cmd` attribute at a commit and
+    /// show its output here.
+    Run(CodeDirective),
+    /// `ajimi::end`: end of the generated code for `change_id`.
+    End {
+        change_id: String,
+        hash: Option<String>,
+    },
+    /// `ajimi::meta::<key> "<value>"`: information generated with the code.
+    Meta { key: String, value: String },
+}
+impl Directive {
+    pub fn meta(key: &str, value: &str) -> Self {
+        Self::Meta {
+            key: key.to_string(),
+            value: value.to_string(),
+        }
+    }
+}
+
+/// An error in a directive. Line and column are 1-based.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DirectiveError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+impl fmt::Display for DirectiveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+#[derive(Debug)]
+struct Arg {
+    column: usize,
+    key: Option<String>,
+    value: String,
+    is_quoted: bool,
+}
+
+struct Lexer<'a> {
+    line: usize,
+    text: &'a str,
+    pos: usize,
+}
+impl<'a> Lexer<'a> {
+    fn error<T>(&self, pos: usize, message: impl Into<String>) -> Result<T, DirectiveError> {
+        Err(DirectiveError {
+            line: self.line,
+            column: pos + 1,
+            message: message.into(),
+        })
+    }
+    fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+    fn skip_whitespace(&mut self) -> bool {
+        let len = self.rest().len() - self.rest().trim_start().len();
+        self.pos += len;
+        len > 0
+    }
+    fn bare(&mut self) -> &'a str {
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| c.is_whitespace() || c == '=' || c == '"')
+            .unwrap_or(rest.len());
+        let len = rest[..len].find("-->").unwrap_or(len);
+        self.pos += len;
+        &rest[..len]
+    }
+    fn quoted(&mut self) -> Result<String, DirectiveError> {
+        let start = self.pos;
+        let mut value = String::new();
+        let mut chars = self.rest().char_indices().skip(1);
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.pos += i + 1;
+                    return Ok(value);
+                }
+                '\\' => match chars.next() {
+                    Some((_, c)) => value.push(c),
+                    None => break,
+                },
+                '-' if self.rest()[i..].starts_with("-->") => {
+                    return self.error(self.pos + i, "`-->` ends the comment; write `-\\->`");
+                }
+                c => value.push(c),
+            }
+        }
+        self.error(start, "unterminated quoted value")
+    }
+    fn value(&mut self) -> Result<(String, bool), DirectiveError> {
+        if self.rest().starts_with('"') {
+            Ok((self.quoted()?, true))
+        } else {
+            let pos = self.pos;
+            let value = self.bare().to_string();
+            if value.is_empty() {
+                return self.error(pos, "value expected");
+            }
+            Ok((value, false))
+        }
+    }
+    fn args(&mut self) -> Result<Vec<Arg>, DirectiveError> {
+        let mut args = Vec::new();
+        loop {
+            let has_space = self.skip_whitespace();
+            if let Some(rest) = self.rest().strip_prefix("-->") {
+                if !rest.trim().is_empty() {
+                    return self.error(self.pos + 3, "unexpected text after -->");
+                }
+                return Ok(args);
+            }
+            if self.rest().is_empty() {
+                return self.error(self.pos, "--> expected");
+            }
+            if !has_space {
+                return self.error(self.pos, "whitespace expected");
+            }
+            let column = self.pos;
+            let (value, is_quoted) = self.value()?;
+            if !is_quoted && self.rest().starts_with('=') {
+                self.pos += 1;
+                let (v, is_quoted) = self.value()?;
+                args.push(Arg {
+                    column,
+                    key: Some(value),
+                    value: v,
+                    is_quoted,
+                });
+            } else {
+                args.push(Arg {
+                    column,
+                    key: None,
+                    value,
+                    is_quoted,
+                });
+            }
+        }
+    }
+}
+
+/// Keys that can be written as a pair of words, e.g. `change_id I0123`.
+const PAIR_KEYS: [&str; 3] = ["change_id", "commit", "hash"];
+
+/// Attributes of `ajimi::code` and `ajimi::run` besides their target.
+const ATTR_KEYS: [&str; 6] = [
+    "body",
+    "context",
+    "function-context",
+    "algorithm",
+    "ignore-space-change",
+    "cmd",
+];
+
+fn attrs_from_args(
+    lexer: &Lexer,
+    args: Vec<Arg>,
+) -> Result<Vec<(usize, String, String)>, DirectiveError> {
+    let mut attrs = Vec::new();
+    let mut args = args.into_iter().peekable();
+    while let Some(arg) = args.next() {
+        match arg.key {
+            Some(key) => attrs.push((arg.column, key, arg.value)),
+            None if !arg.is_quoted && PAIR_KEYS.contains(&arg.value.as_str()) => {
+                match args.next_if(|next| next.key.is_none()) {
+                    Some(value) => attrs.push((arg.column, arg.value, value.value)),
+                    None => {
+                        return lexer.error(arg.column, format!("value for {} expected", arg.value))
+                    }
+                }
+            }
+            None if arg.is_quoted => return lexer.error(arg.column, "unexpected quoted value"),
+            None => attrs.push((arg.column, arg.value, "true".to_string())),
+        }
+    }
+    Ok(attrs)
+}
+
+/// Parses an HTML comment on `line`. Returns Ok(None) if the comment is not an
+/// ajimi directive.
+pub fn parse_comment(text: &str, line: usize) -> Result<Option<Directive>, DirectiveError> {
+    let text = text.trim();
+    let Some(rest) = text.strip_prefix("<!--") else {
+        return Ok(None);
+    };
+    let mut lexer = Lexer { line, text, pos: 4 };
+    lexer.skip_whitespace();
+    if !rest.trim_start().starts_with("ajimi::") {
+        return Ok(None);
+    }
+    let kind_pos = lexer.pos;
+    let kind = lexer.bare().to_string();
+    let args = lexer.args()?;
+    let kind = kind.strip_prefix("ajimi::").unwrap_or_default();
+    match kind.split_once("::") {
+        Some(("meta", key)) if !key.is_empty() => {
+            let [arg] = <[Arg; 1]>::try_from(args).or_else(|args| {
+                lexer.error(
+                    args.get(1).map(|a| a.column).unwrap_or(lexer.pos),
+                    "exactly one value expected",
+                )
+            })?;
+            if arg.key.is_some() {
+                return lexer.error(arg.column, "value expected");
+            }
+            return Ok(Some(Directive::meta(key, &arg.value)));
+        }
+        Some(_) => return lexer.error(kind_pos, format!("unknown directive: ajimi::{kind}")),
+        None => {}
+    }
+    let mut attrs = attrs_from_args(&lexer, args)?;
+    let mut take = |key: &str| {
+        attrs
+            .iter()
+            .position(|(_, k, _)| k == key)
+            .map(|i| attrs.remove(i).2)
+    };
+    match kind {
+        "code" | "run" => {
+            let target = match (take("change_id"), take("commit")) {
+                (Some(change_id), None) => Target::ChangeId(change_id),
+                (None, Some(commit)) if kind == "code" => Target::Commit(commit),
+                _ if kind == "run" => return lexer.error(kind_pos, "change_id is required"),
+                _ => {
+                    return lexer.error(kind_pos, "either change_id or commit is required");
+                }
+            };
+            for (column, key, _) in &attrs {
+                if PAIR_KEYS.contains(&key.as_str()) {
+                    return lexer.error(*column, format!("unexpected {key}"));
+                }
+                if !ATTR_KEYS.contains(&key.as_str()) {
+                    return lexer.error(*column, format!("unknown attribute: {key}"));
+                }
+            }
+            if kind == "run" && !attrs.iter().any(|(_, k, _)| k == "cmd") {
+                return lexer.error(kind_pos, "cmd is required");
+            }
+            let directive = CodeDirective {
+                target,
+                attrs: attrs.into_iter().map(|(_, k, v)| (k, v)).collect(),
+            };
+            Ok(Some(match kind {
+                "run" => Directive::Run(directive),
+                _ => Directive::Code(directive),
+            }))
+        }
+        "end" => {
+            let Some(change_id) = take("change_id") else {
+                return lexer.error(kind_pos, "change_id is required");
+            };
+            let hash = take("hash");
+            if let Some((column, key, _)) = attrs.first() {
+                return lexer.error(*column, format!("unknown attribute: {key}"));
+            }
+            Ok(Some(Directive::End { change_id, hash }))
+        }
+        _ => lexer.error(kind_pos, format!("unknown directive: ajimi::{kind}")),
+    }
+}
+
+/// `value` in quotes, with `\`, `"` and the second `-` of `--` escaped so
+/// that the value can not end the comment.
+fn quote(value: &str) -> String {
+    let mut quoted = String::from('"');
+    for c in value.chars() {
+        if c == '\\' || c == '"' || (c == '-' && quoted.ends_with('-')) {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn write_value(f: &mut fmt::Formatter, value: &str) -> fmt::Result {
+    if value.is_empty()
+        || value.contains(|c: char| c.is_whitespace() || c == '"' || c == '=' || c == '\\')
+        || value.contains("--")
+    {
+        f.write_str(&quote(value))
+    } else {
+        f.write_str(value)
+    }
+}
+
+impl fmt::Display for Directive {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("<!-- ajimi::")?;
+        match self {
+            Directive::Code(CodeDirective { target, attrs })
+            | Directive::Run(CodeDirective { target, attrs }) => {
+                let (key, value) = match target {
+                    Target::ChangeId(v) => ("change_id", v),
+                    Target::Commit(v) => ("commit", v),
+                };
+                let kind = match self {
+                    Directive::Run(_) => "run",
+                    _ => "code",
+                };
+                write!(f, "{kind} {key} ")?;
+                write_value(f, value)?;
+                for (key, value) in attrs {
+                    write!(f, " {key}")?;
+                    if value != "true" {
+                        f.write_str("=")?;
+                        write_value(f, value)?;
+                    }
+                }
+            }
+            Directive::End { change_id, hash } => {
+                f.write_str("end change_id ")?;
+                write_value(f, change_id)?;
+                if let Some(hash) = hash {
+                    f.write_str(" hash=")?;
+                    write_value(f, hash)?;
+                }
+            }
+            Directive::Meta { key, value } => {
+                write!(f, "meta::{key} {}", quote(value))?;
+            }
+        }
+        f.write_str(" -->")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn code(target: Target, attrs: &[(&str, &str)]) -> Directive {
+        Directive::Code(CodeDirective {
+            target,
+            attrs: attrs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        })
+    }
+
+    #[test]
+    fn parse_directives() {
+        let change_id = || Target::ChangeId("I0".to_string());
+        assert_eq!(parse_comment("<!-- source: a.png -->", 1), Ok(None));
+        for s in [
+            "<!-- ajimi::code change_id I0 -->",
+            "<!--ajimi::code\tchange_id  I0-->",
+            "<!-- ajimi::code change_id=I0 -->",
+            "<!-- ajimi::code change_id \"I0\" -->",
+        ] {
+            assert_eq!(parse_comment(s, 1), Ok(Some(code(change_id(), &[]))), "{s}");
+        }
+        assert_eq!(
+            parse_comment(
+                "<!-- ajimi::code commit 0123 context=10 function-context -->",
+                1
+            ),
+            Ok(Some(code(
+                Target::Commit("0123".to_string()),
+                &[("context", "10"), ("function-context", "true")]
+            )))
+        );
+        assert_eq!(
+            parse_comment("<!-- ajimi::end change_id I0 hash 01ab -->", 1),
+            parse_comment("<!-- ajimi::end change_id I0 hash=01ab -->", 1),
+        );
+        assert_eq!(
+            parse_comment(r#"<!-- ajimi::meta::title "Say \"hi\" -\->" -->"#, 1),
+            Ok(Some(Directive::meta("title", "Say \"hi\" -->")))
+        );
+        let error = |s, column| parse_comment(s, 3).unwrap_err().column == column;
+        assert!(error("<!-- ajimi::code change_id I0", 30));
+        assert!(error("<!-- ajimi::code -->", 6));
+        assert!(error("<!-- ajimi::code change_id -->", 18));
+        assert!(error("<!-- ajimi::foo change_id I0 -->", 6));
+        assert!(error("<!-- ajimi::end change_id I0 foo=bar -->", 30));
+        assert!(error("<!-- ajimi::meta::title \"a -->", 28));
+        assert!(error("<!-- ajimi::meta::title \"a -->\" -->", 28));
+        assert!(error("<!-- ajimi::meta::title a b -->", 27));
+        assert!(error("<!-- ajimi::code change_id I0 --> x", 34));
+        assert!(error("<!-- ajimi::run change_id I0 -->", 6));
+        assert!(error("<!-- ajimi::run commit 0123 cmd=ls -->", 6));
+        // typos are not ignored.
+        assert!(error("<!-- ajimi::code change_id I0 contxt=5 -->", 31));
+        assert!(error(
+            "<!-- ajimi::code change_id I0 function_context -->",
+            31
+        ));
+    }
+
+    #[test]
+    fn display_round_trip() {
+        for d in [
+            code(
+                Target::ChangeId("I0".to_string()),
+                &[("context", "1"), ("body", "a b")],
+            ),
+            Directive::End {
+                change_id: "I0".to_string(),
+                hash: Some("01ab".to_string()),
+            },
+            Directive::meta("title", "Fix \"quotes\" and \\ -->"),
+            Directive::meta("title", ""),
+            Directive::Run(CodeDirective {
+                target: Target::ChangeId("I0".to_string()),
+                attrs: [("cmd".to_string(), "cargo run --quiet".to_string())].into(),
+            }),
+        ] {
+            assert_eq!(parse_comment(&d.to_string(), 1), Ok(Some(d)));
+        }
+        assert_eq!(
+            code(Target::ChangeId("I0".to_string()), &[]).to_string(),
+            "<!-- ajimi::code change_id I0 -->"
+        );
+        assert_eq!(
+            Directive::meta("title", "a --> b ---").to_string(),
+            r#"<!-- ajimi::meta::title "a -\-> b -\-\-" -->"#
+        );
+    }
+}
@@ -0,0 +1,543 @@
+use crate::directive::parse_comment;
+use crate::directive::CodeDirective;
+use crate::directive::Directive;
+use crate::directive::DirectiveError;
+use crate::directive::Target;
+use crate::markdown::Outline;
+use sha2::Digest;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
+use std::ops::Range;
+
+/// A run of whole lines in a document.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Piece {
+    /// byte range in the source the document was parsed from
+    pub range: Range<usize>,
+    /// 1-based line number of the first line in the source
+    pub line: usize,
+    /// current text, including the trailing newline if any
+    pub text: String,
+}
+
+/// An `ajimi::code` marker and the code generated for it.
+///
+/// ```text
+/// <!-- ajimi::code change_id I0123 -->         <- marker
+/// <!-- ajimi::meta::title "Add foo" -->        <- meta
+///                                              <- body
+/// ```rust                                      <- body
+/// ...                                          <- body
+/// <!-- ajimi::end change_id I0123 hash ... --> <- end
+/// ```
+///
+/// A marker without a matching end marker (e.g. a marker that has just been
+/// written by hand) has no generated parts.
+///
+/// An `ajimi::run` marker makes a region of the same shape, with the output
+/// of a command instead of a diff.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub kind: RegionKind,
+    pub marker: Piece,
+    pub directive: CodeDirective,
+    pub meta: Vec<Piece>,
+    pub body: Option<Piece>,
+    pub end: Option<Piece>,
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegionKind {
+    /// `ajimi::code`
+    Code,
+    /// `ajimi::run`
+    Run,
+}
+
+impl Region {
+    pub fn change_id(&self) -> Option<&str> {
+        match &self.directive.target {
+            Target::ChangeId(change_id) => Some(change_id),
+            Target::Commit(_) => None,
+        }
+    }
+    /// The commit hash in the marker, for markers not converted to change_id yet.
+    pub fn commit(&self) -> Option<&str> {
+        match &self.directive.target {
+            Target::ChangeId(_) => None,
+            Target::Commit(commit) => Some(commit),
+        }
+    }
+    /// Value of `hash` in the end marker.
+    pub fn hash(&self) -> Option<String> {
+        let end = self.end.as_ref()?;
+        match parse_comment(&end.text, end.line) {
+            Ok(Some(Directive::End { hash, .. })) => hash,
+            _ => None,
+        }
+    }
+    /// Value and line of the `ajimi::meta::<key>` directive of the region.
+    pub fn meta(&self, key: &str) -> Option<(String, usize)> {
+        self.meta
+            .iter()
+            .find_map(|piece| match parse_comment(&piece.text, piece.line) {
+                Ok(Some(Directive::Meta { key: k, value })) if k == key => {
+                    Some((value, piece.line))
+                }
+                _ => None,
+            })
+    }
+    /// Text between the marker and the end marker.
+    pub fn generated(&self) -> String {
+        self.meta
+            .iter()
+            .chain(self.body.iter())
+            .map(|piece| piece.text.as_str())
+            .collect()
+    }
+    /// True if the generated text does not match the hash in the end marker,
+    /// i.e. it was edited by hand after `fix` generated it. End markers
+    /// without hash are not checked.
+    pub fn is_edited(&self) -> bool {
+        self.hash()
+            .is_some_and(|hash| hash != generated_hash(&self.generated()))
+    }
+    /// Replaces the marker. The end marker follows a new change_id.
+    pub fn set_directive(&mut self, directive: CodeDirective) {
+        let marker = match self.kind {
+            RegionKind::Code => Directive::Code(directive.clone()),
+            RegionKind::Run => Directive::Run(directive.clone()),
+        }
+        .to_string();
+        self.marker.text = with_newline_as(&marker, &self.marker.text);
+        let hash = self.hash();
+        if let (Target::ChangeId(change_id), Some(end)) = (&directive.target, &mut self.end) {
+            let end_marker = Directive::End {
+                change_id: change_id.clone(),
+                hash,
+            };
+            end.text = with_newline_as(&end_marker.to_string(), &end.text);
+        }
+        self.directive = directive;
+    }
+    /// Replaces the generated parts of the region. `body` is the text between
+    /// the meta directives and the end marker.
+    pub fn set_generated(&mut self, meta: &[Directive], body: &str, end: &Directive) {
+        let end_marker = end.to_string();
+        if !self.marker.text.ends_with('\n') {
+            self.marker.text.push('\n');
+        }
+        let line = self.marker.line + 1;
+        let offset = self.marker.range.end;
+        let empty_at = |offset: usize| Piece {
+            range: offset..offset,
+            line,
+            text: String::new(),
+        };
+        let mut meta_pieces = self
+            .meta
+            .iter()
+            .cloned()
+            .chain(std::iter::repeat(empty_at(offset)));
+        self.meta = meta
+            .iter()
+            .map(|directive| Piece {
+                text: format!("{directive}\n"),
+                ..meta_pieces.next().unwrap()
+            })
+            .collect();
+        let body_piece = self.body.take().unwrap_or(empty_at(offset));
+        self.body = Some(Piece {
+            text: body.to_string(),
+            ..body_piece
+        });
+        let end_text = match &self.end {
+            Some(end) => with_newline_as(&end_marker, &end.text),
+            None => format!("{end_marker}\n"),
+        };
+        let end_piece = self.end.take().unwrap_or(empty_at(offset));
+        self.end = Some(Piece {
+            text: end_text,
+            ..end_piece
+        });
+    }
+}
+
+/// A part of a document.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Block {
+    /// Lines outside of fences and regions.
+    Prose(Piece),
+    /// A fenced code block outside of regions.
+    Fence(Piece),
+    Region(Region),
+}
+
+/// A Markdown file of a book, split into prose, code fences and ajimi regions.
+///
+/// The document is lossless: `to_string()` returns the source it was parsed
+/// from as long as nothing has been edited.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Document {
+    blocks: Vec<Block>,
+    errors: Vec<DirectiveError>,
+}
+impl Document {
+    pub fn parse(src: &str) -> Self {
+        let outline = Outline::parse(src);
+        let line_starts: Vec<usize> = std::iter::once(0)
+            .chain(src.match_indices('\n').map(|(i, _)| i + 1))
+            .filter(|start| *start < src.len())
+            .collect();
+        let num_lines = line_starts.len();
+        let line_end = |ln: usize| line_starts.get(ln + 1).copied().unwrap_or(src.len());
+        let piece = |lines: Range<usize>| {
+            let range = line_starts[lines.start]..line_end(lines.end - 1);
+            Piece {
+                line: lines.start + 1,
+                text: src[range.clone()].to_string(),
+                range,
+            }
+        };
+        let mut errors = Vec::new();
+        // 0-based line indexes
+        let mut directives: HashMap<usize, Directive> = HashMap::new();
+        // `ajimi::meta::` lines that do not parse, e.g. titles with quotes
+        // written unescaped by older versions of fix.
+        let mut broken_meta: HashSet<usize> = HashSet::new();
+        for c in &outline.comments {
+            match parse_comment(&c.text, c.line) {
+                Ok(Some(directive)) => {
+                    directives.insert(c.line - 1, directive);
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    if is_meta_comment(&c.text) {
+                        broken_meta.insert(c.line - 1);
+                    }
+                    errors.push(e);
+                }
+            }
+        }
+        let is_meta = |ln: usize| {
+            matches!(directives.get(&ln), Some(Directive::Meta { .. })) || broken_meta.contains(&ln)
+        };
+        // lines of meta directives in regions, which are generated.
+        let mut meta_lines: HashSet<usize> = HashSet::new();
+        let fences: HashMap<usize, usize> = outline
+            .fences
+            .iter()
+            .map(|f| {
+                let last_line = line_starts.partition_point(|s| *s < f.range.end) - 1;
+                (f.line - 1, last_line.max(f.line - 1))
+            })
+            .collect();
+        let code_directive = |ln: usize| match directives.get(&ln) {
+            Some(Directive::Code(directive)) => Some((RegionKind::Code, directive)),
+            Some(Directive::Run(directive)) => Some((RegionKind::Run, directive)),
+            _ => None,
+        };
+
+        let mut blocks = Vec::new();
+        let mut prose_start = 0;
+        let mut ln = 0;
+        while ln < num_lines {
+            let block_end;
+            let block = if let Some(last_line) = fences.get(&ln) {
+                block_end = last_line + 1;
+                Block::Fence(piece(ln..block_end))
+            } else if let Some((kind, directive)) = code_directive(ln) {
+                let marker = piece(ln..ln + 1);
+                let end_line = (ln + 1..num_lines)
+                    .take_while(|l| code_directive(*l).is_none())
+                    .find(|l| match (&directive.target, directives.get(l)) {
+                        (Target::ChangeId(id), Some(Directive::End { change_id, .. })) => {
+                            id == change_id
+                        }
+                        _ => false,
+                    });
+                let directive = directive.clone();
+                if let Some(end_line) = end_line {
+                    let meta_end = (ln + 1..end_line)
+                        .find(|l| !is_meta(*l))
+                        .unwrap_or(end_line);
+                    meta_lines.extend(ln + 1..meta_end);
+                    block_end = end_line + 1;
+                    Block::Region(Region {
+                        kind,
+                        marker,
+                        directive,
+                        meta: (ln + 1..meta_end).map(|l| piece(l..l + 1)).collect(),
+                        body: (meta_end < end_line).then(|| piece(meta_end..end_line)),
+                        end: Some(piece(end_line..end_line + 1)),
+                    })
+                } else {
+                    block_end = ln + 1;
+                    Block::Region(Region {
+                        kind,
+                        marker,
+                        directive,
+                        meta: Vec::new(),
+                        body: None,
+                        end: None,
+                    })
+                }
+            } else {
+                ln += 1;
+                continue;
+            };
+            if prose_start < ln {
+                blocks.push(Block::Prose(piece(prose_start..ln)));
+            }
+            blocks.push(block);
+            ln = block_end;
+            prose_start = ln;
+        }
+        if prose_start < num_lines {
+            blocks.push(Block::Prose(piece(prose_start..num_lines)));
+        }
+        // broken meta directives in regions are regenerated by fix.
+        errors.retain(|e| !meta_lines.contains(&(e.line - 1)));
+        Self { blocks, errors }
+    }
+    /// Errors in the directives, which are treated as prose. Meta directives
+    /// of regions are generated, so their errors are left out.
+    pub fn errors(&self) -> &[DirectiveError] {
+        &self.errors
+    }
+    pub fn blocks(&self) -> &[Block] {
+        &self.blocks
+    }
+    /// Regions of `ajimi::code` markers.
+    pub fn regions(&self) -> impl Iterator<Item = &Region> {
+        self.all_regions()
+            .filter(|region| region.kind == RegionKind::Code)
+    }
+    /// Regions of `ajimi::run` markers.
+    pub fn runs(&self) -> impl Iterator<Item = &Region> {
+        self.all_regions()
+            .filter(|region| region.kind == RegionKind::Run)
+    }
+    /// Regions of all kinds.
+    pub fn all_regions(&self) -> impl Iterator<Item = &Region> {
+        self.blocks.iter().filter_map(|b| match b {
+            Block::Region(region) => Some(region),
+            _ => None,
+        })
+    }
+    /// Inserts `text` (whole lines) before `blocks()[index]`, or at the end if
+    /// `index` is the number of blocks. The document is parsed again, so
+    /// line numbers and ranges refer to the new text afterwards.
+    pub fn insert(&mut self, index: usize, text: &str) {
+        let mut src: String = self.blocks[..index].iter().map(|b| b.to_string()).collect();
+        if !src.is_empty() && !src.ends_with('\n') {
+            src.push('\n');
+        }
+        src += text;
+        src.extend(self.blocks[index..].iter().map(|b| b.to_string()));
+        *self = Self::parse(&src);
+    }
+    pub fn regions_mut(&mut self) -> impl Iterator<Item = &mut Region> {
+        self.all_regions_mut()
+            .filter(|region| region.kind == RegionKind::Code)
+    }
+    pub fn runs_mut(&mut self) -> impl Iterator<Item = &mut Region> {
+        self.all_regions_mut()
+            .filter(|region| region.kind == RegionKind::Run)
+    }
+    pub fn all_regions_mut(&mut self) -> impl Iterator<Item = &mut Region> {
+        self.blocks.iter_mut().filter_map(|b| match b {
+            Block::Region(region) => Some(region),
+            _ => None,
+        })
+    }
+}
+impl fmt::Display for Block {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Block::Prose(piece) | Block::Fence(piece) => f.write_str(&piece.text),
+            Block::Region(region) => {
+                f.write_str(&region.marker.text)?;
+                f.write_str(&region.generated())?;
+                if let Some(end) = &region.end {
+                    f.write_str(&end.text)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+impl fmt::Display for Document {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for block in &self.blocks {
+            write!(f, "{block}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns a short hash of the text generated for a region, which is stored in
+/// its end marker to detect manual edits.
+pub fn generated_hash(generated: &str) -> String {
+    let generated = generated.strip_suffix('\n').unwrap_or(generated);
+    let digest = Sha256::digest(generated.as_bytes());
+    digest[..8].iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// True if `comment` is an `ajimi::meta::` directive, even a broken one.
+fn is_meta_comment(comment: &str) -> bool {
+    comment
+        .trim_start()
+        .strip_prefix("<!--")
+        .is_some_and(|rest| rest.trim_start().starts_with("ajimi::meta::"))
+}
+
+fn with_newline_as(line: &str, original: &str) -> String {
+    if original.ends_with('\n') {
+        format!("{line}\n")
+    } else {
+        line.to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const BOOK: &str = "# Title
+
+<!-- ajimi::code change_id I0 -->
+<!-- ajimi::meta::title \"t\" -->
+
+```rust
+a
+```
+
+<!-- ajimi::end change_id I0 hash 0 -->
+text
+```md
+<!-- ajimi::code change_id I1 -->
+```
+<!-- ajimi::code commit 0123 -->
+tail";
+
+    #[test]
+    fn parse_and_round_trip() {
+        let doc = Document::parse(BOOK);
+        assert_eq!(doc.to_string(), BOOK);
+        let kinds: Vec<(&str, usize)> = doc
+            .blocks()
+            .iter()
+            .map(|b| match b {
+                Block::Prose(p) => ("prose", p.line),
+                Block::Fence(p) => ("fence", p.line),
+                Block::Region(r) => ("region", r.marker.line),
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ("prose", 1),
+                ("region", 3),
+                ("prose", 11),
+                ("fence", 12),
+                ("region", 15),
+                ("prose", 16)
+            ]
+        );
+        let regions: Vec<&Region> = doc.regions().collect();
+        assert_eq!(regions[0].change_id(), Some("I0"));
+        assert_eq!(regions[0].hash().as_deref(), Some("0"));
+        assert_eq!(regions[0].meta.len(), 1);
+        assert_eq!(
+            regions[0].body.as_ref().unwrap().text,
+            "\n```rust\na\n```\n\n"
+        );
+        assert_eq!(
+            &BOOK[regions[0].end.as_ref().unwrap().range.clone()],
+            "<!-- ajimi::end change_id I0 hash 0 -->\n"
+        );
+        assert!(regions[0].is_edited());
+        assert_eq!(regions[1].commit(), Some("0123"));
+        assert!(regions[1].end.is_none());
+        for src in ["", "\n", "a", "<!-- ajimi::code change_id I0 -->"] {
+            assert_eq!(Document::parse(src).to_string(), src);
+        }
+    }
+
+    #[test]
+    fn broken_meta_in_regions_is_generated() {
+        let meta = "<!-- ajimi::meta::title \"Say \"hi\" -- now\" -->\n";
+        let doc = Document::parse(&format!(
+            "<!-- ajimi::code change_id I0 -->\n{meta}body\n<!-- ajimi::end change_id I0 -->\n"
+        ));
+        assert_eq!(doc.errors(), &[]);
+        let region = doc.regions().next().unwrap();
+        assert_eq!(region.meta.len(), 1);
+        assert!(!region.is_edited());
+        // outside of regions, it is an error as any other.
+        assert_eq!(Document::parse(meta).errors().len(), 1);
+    }
+
+    #[test]
+    fn edit_regions() {
+        let mut doc = Document::parse(BOOK);
+        for region in doc.regions_mut() {
+            region.set_directive(CodeDirective {
+                target: Target::ChangeId("I2".to_string()),
+                attrs: Default::default(),
+            });
+            let body = "\n```rust\nb\n```\n\n";
+            let meta = vec![Directive::meta("title", "u")];
+            let hash = generated_hash(&format!("{}\n{body}", meta[0]));
+            region.set_generated(
+                &meta,
+                body,
+                &Directive::End {
+                    change_id: "I2".to_string(),
+                    hash: Some(hash),
+                },
+            );
+            assert!(!region.is_edited());
+        }
+        let expected = "# Title
+
+<!-- ajimi::code change_id I2 -->
+<!-- ajimi::meta::title \"u\" -->
+
+```rust
+b
+```
+
+<!-- ajimi::end change_id I2 hash=HASH -->
+text
+```md
+<!-- ajimi::code change_id I1 -->
+```
+<!-- ajimi::code change_id I2 -->
+<!-- ajimi::meta::title \"u\" -->
+
+```rust
+b
+```
+
+<!-- ajimi::end change_id I2 hash=HASH -->
+tail";
+        let hash = doc.regions().next().unwrap().hash().unwrap();
+        assert_eq!(doc.to_string(), expected.replace("HASH", &hash));
+    }
+
+    #[test]
+    fn insert_blocks() {
+        let mut doc = Document::parse("a\n<!-- ajimi::code change_id I0 -->\nb");
+        doc.insert(1, "<!-- ajimi::code change_id I1 -->\n");
+        doc.insert(doc.blocks().len(), "c\n");
+        assert_eq!(
+            doc.to_string(),
+            "a\n<!-- ajimi::code change_id I1 -->\n<!-- ajimi::code change_id I0 -->\nb\nc\n"
+        );
+        let ids: Vec<&str> = doc.regions().filter_map(|r| r.change_id()).collect();
+        assert_eq!(ids, vec!["I1", "I0"]);
+    }
+}
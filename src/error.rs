@@ -0,0 +1,66 @@
+use std::path::PathBuf;
+
+/// Errors returned by ajimi. The library never panics on user input; every
+/// failure is reported as one of these.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// A git command could not be run, failed, or printed something unexpected.
+    #[error("git: {message}")]
+    Git { message: String },
+    /// Invalid input in a file. `line` is 1-based.
+    #[error("{file}:{line}: {message}")]
+    Parse {
+        file: String,
+        line: usize,
+        message: String,
+    },
+    /// A diff that ajimi does not know how to render.
+    #[error("invalid patch: {message}")]
+    InvalidPatch { message: String },
+    /// A file in a patch whose code block language is unknown.
+    #[error("file type unknown for {path}")]
+    UnknownFileType { path: String },
+    /// A commit, Change-Id or line that does not exist.
+    #[error("not found: {what}")]
+    NotFound { what: String },
+    #[error("{path:?}: {source}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    /// Invalid `ajimi.toml`.
+    #[error("config: {message}")]
+    Config { message: String },
+    /// Generated code in a book file was edited by hand.
+    #[error("{file}: generated code was edited manually ({}). Move the edits out of the generated region, or use --force to discard them", regions.join(", "))]
+    EditedRegions { file: String, regions: Vec<String> },
+    /// `fix --dry-run` found files to be changed.
+    #[error("{num_files} file(s) would be changed by fix")]
+    FixNeeded { num_files: usize },
+    /// `verify-commits` found steps of the book where the command fails.
+    #[error("the command failed at {num_steps} step(s) of the book")]
+    StepsFailed { num_steps: usize },
+    /// A worker of `verify-commits` panicked.
+    #[error("a verify-commits worker panicked: {message}")]
+    WorkerPanicked { message: String },
+    /// `check` found issues, which have been reported already.
+    #[error("Found some issues. Please fix them and try again!")]
+    ChecksFailed,
+}
+impl Error {
+    pub(crate) fn git(message: impl Into<String>) -> Self {
+        Self::Git {
+            message: message.into(),
+        }
+    }
+    pub(crate) fn not_found(what: impl Into<String>) -> Self {
+        Self::NotFound { what: what.into() }
+    }
+    pub(crate) fn io(path: impl Into<PathBuf>) -> impl FnOnce(std::io::Error) -> Self {
+        let path = path.into();
+        move |source| Self::Io { path, source }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -1,11 +1,36 @@
+use crate::check::book_markers;
+use crate::check::neighbours;
+use crate::check::Diagnostic;
+use crate::check::DiagnosticKind;
+use crate::check::Marker;
+use crate::config::Config;
+use crate::directive::CodeDirective;
+use crate::directive::Directive;
+use crate::directive::Target;
+use crate::document::generated_hash;
+use crate::document::Block;
+use crate::document::Document;
+use crate::remap::load_remap;
+use crate::remap::Remap;
+use crate::render::render_body;
+use crate::render::render_patch;
+use crate::render::RenderOptions;
 use crate::repo::CommitResolver;
+use crate::repo::DiffAlgorithm;
+use crate::repo::DiffOptions;
 use crate::repo::GitRepo;
-use anyhow::anyhow;
-use anyhow::Context;
-use anyhow::Result;
+use crate::skip::SkipRules;
+use crate::Book;
+use crate::BookFile;
+use crate::Error;
+use crate::Result;
 use argh::FromArgs;
+use serde::Deserialize;
+use sha2::Digest;
+use sha2::Sha256;
+use std::collections::BTreeMap;
 use std::collections::HashSet;
-use std::io::Write;
+use std::path::Path;
 use std::path::PathBuf;
 
 #[derive(FromArgs, PartialEq, Debug)]
@@ -18,421 +43,717 @@ pub struct Args {
     /// markdown files to be fixed
     #[argh(positional)]
     files: Vec<String>,
+    /// print a unified diff of the changes instead of writing the files
+    /// (exits with an error if any file would be changed)
+    #[argh(switch)]
+    dry_run: bool,
+    /// overwrite generated code even if it was edited manually
+    #[argh(switch)]
+    force: bool,
+    /// insert markers for commits that are neither in the book nor skipped,
+    /// with a draft paragraph taken from the commit message
+    #[argh(switch)]
+    insert_missing: bool,
+    /// config file (default: ajimi.toml if it exists)
+    #[argh(option)]
+    config: Option<PathBuf>,
+    /// file mapping old change_ids to new ones, one pair per line, to
+    /// rewrite markers after the history was rebuilt
+    #[argh(option)]
+    remap: Option<PathBuf>,
+    /// lines of context around changes in generated code (default:
+    /// diff.context in the config, or 3)
+    #[argh(option, short = 'U')]
+    context: Option<usize>,
+    /// show the whole function around changes in generated code
+    #[argh(switch, short = 'W')]
+    function_context: bool,
+    /// diff algorithm for generated code: myers, minimal, patience or
+    /// histogram (default: diff.algorithm in the config, or git's own)
+    #[argh(option)]
+    diff_algorithm: Option<DiffAlgorithm>,
+    /// ignore changes in the amount of whitespace in generated code
+    #[argh(switch, short = 'b')]
+    ignore_space_change: bool,
+    /// run the commands of `ajimi::run` markers whose output is out of date
+    /// (default: run.enabled in the config). Ignored with --dry-run.
+    #[argh(switch)]
+    run: bool,
 }
 impl Args {
     pub fn run(&self) -> Result<()> {
         let repo = GitRepo::new(self.code.clone());
-        for file in &self.files {
-            eprintln!("fix: {file}");
-            fix_file(&repo, file)?;
+        let mut book = Book::load(&self.files)?;
+        let config = Config::find(self.config.as_deref())?;
+        let mut diff = config.diff;
+        if self.context.is_some() {
+            diff.context = self.context;
+        }
+        diff.function_context |= self.function_context;
+        if self.diff_algorithm.is_some() {
+            diff.algorithm = self.diff_algorithm;
+        }
+        diff.ignore_space_change |= self.ignore_space_change;
+        let mut run = config.run;
+        run.enabled = (run.enabled || self.run) && !self.dry_run;
+        let options = FixOptions {
+            force: self.force,
+            insert_missing: self.insert_missing,
+            skip: config.skip,
+            remap: self
+                .remap
+                .as_deref()
+                .map(load_remap)
+                .transpose()?
+                .unwrap_or_default(),
+            run,
+            diff,
+            ..Default::default()
+        };
+        let diagnostics = book.fix(&repo, &options)?;
+        for diagnostic in &diagnostics {
+            eprintln!("{diagnostic}");
+        }
+        if self.dry_run {
+            let changed: Vec<&BookFile> = book.files().iter().filter(|f| f.is_changed()).collect();
+            for file in &changed {
+                print!("{}", file.diff());
+            }
+            if !changed.is_empty() {
+                return Err(Error::FixNeeded {
+                    num_files: changed.len(),
+                });
+            }
+        } else {
+            book.save()?;
+        }
+        // the book is only partly fixed.
+        if diagnostics.iter().any(|d| d.kind.is_error()) {
+            return Err(Error::ChecksFailed);
         }
         Ok(())
     }
 }
 
-fn fix_file<T: CommitResolver>(repo: &T, path: &str) -> Result<()> {
-    let s = std::fs::read_to_string(path).expect("Failed to open a file");
-    let lines: Vec<String> = s.split('\n').map(|s| s.to_string()).collect();
-    let lines = replace_commit_id_with_change_id(repo, lines)?;
-    let lines = remove_generated_lines(lines)?;
-    let lines = insert_commit_diff_with_change_id(repo, lines)?;
-    let s = lines.join("\n");
-    std::fs::File::create(path)?
-        .write_all(s.as_bytes())
-        .context("Failed to write a file")?;
-    Ok(())
+/// Options for [`Book::fix`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FixOptions {
+    /// overwrite generated code even if it was edited manually
+    pub force: bool,
+    pub render: RenderOptions,
+    /// insert markers for commits missing in the book
+    pub insert_missing: bool,
+    /// commits not to insert with `insert_missing`
+    pub skip: SkipRules,
+    /// old change_ids in markers to replace with new ones
+    pub remap: Remap,
+    pub run: RunOptions,
+    /// diff options of markers without their own
+    pub diff: DiffOptions,
 }
 
-fn replace_commit_id_with_change_id<T: CommitResolver>(
-    commit_resolver: &T,
-    lines: Vec<String>,
-) -> Result<Vec<String>> {
-    let mut lines_updated: Vec<String> = Vec::new();
-    for (ln, line) in lines.into_iter().enumerate() {
-        if line.contains("ajimi::code") && line.starts_with("<!--") {
-            if let Some(commit) = line.split(' ').skip_while(|s| *s != "commit").nth(1) {
-                if let Ok(change_id) = commit_resolver.change_id_from_commit_id(commit) {
-                    let line_updated = format!("<!-- ajimi::code change_id {change_id} -->");
-                    lines_updated.push(line_updated);
-                    continue;
-                } else {
-                    eprintln!("Invalid commit at line {ln}: {}", line);
-                    lines_updated.push(line);
-                    continue;
-                }
+/// Settings for `ajimi::run` markers in `ajimi.toml`.
+///
+/// ```toml
+/// [run]
+/// enabled = true
+/// cache_dir = ".ajimi-cache"
+/// ```
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RunOptions {
+    /// run the commands, which come from the book, rather than only taking
+    /// their output from the cache
+    pub enabled: bool,
+    /// directory to keep the output of commands in, by commit hash and
+    /// command, so that they are not run again. Relative to the config file.
+    pub cache_dir: Option<PathBuf>,
+}
+
+/// A marker with a draft paragraph for a commit missing in the book.
+struct Draft {
+    /// position in the history, oldest first
+    order: usize,
+    change_id: String,
+    text: String,
+}
+
+/// Inserts a marker and a draft paragraph for each commit that is neither in
+/// the book nor skipped, next to the markers of its neighbours in the history.
+/// Returns a diagnostic for each file with inserted markers.
+pub(crate) fn insert_missing_markers<T: CommitResolver>(
+    book: &mut Book,
+    repo: &T,
+    skip: &SkipRules,
+) -> Result<Vec<Diagnostic>> {
+    let mut diagnostics = Vec::new();
+    let commits = repo.all_commit_summary_in_tree()?;
+    // (file, block index) => drafts in history order
+    let mut insertions: BTreeMap<(usize, usize), Vec<Draft>> = BTreeMap::new();
+    let markers = book_markers(book, &commits);
+    let in_book: HashSet<&str> = markers.iter().map(|m| m.change_id).collect();
+    for (order, c) in commits.iter().rev().enumerate() {
+        if in_book.contains(c.change_id.as_str()) || skip.reason(c)?.is_some() {
+            continue;
+        }
+        let block_index = |m: &Marker| {
+            book.files()[m.file]
+                .document()
+                .blocks()
+                .iter()
+                .position(|b| matches!(b, Block::Region(r) if r.marker.line == m.line))
+        };
+        let place = match neighbours(&markers, order) {
+            (Some(prev), _) => block_index(prev).map(|i| (prev.file, i + 1)),
+            (None, Some(next)) => block_index(next).map(|i| (next.file, i)),
+            (None, None) => book
+                .files()
+                .len()
+                .checked_sub(1)
+                .map(|last| (last, book.files()[last].document().blocks().len())),
+        };
+        let Some(place) = place else {
+            continue;
+        };
+        let body = match repo.commit_from_change_id(&c.change_id) {
+            Ok(commit) => commit.body,
+            Err(e) => {
+                diagnostics.push(
+                    Diagnostic::new(
+                        DiagnosticKind::UnknownChangeId,
+                        format!("failed to get the commit message: {e}"),
+                    )
+                    .with_change_id(&c.change_id),
+                );
+                String::new()
             }
+        };
+        let marker = Directive::Code(CodeDirective {
+            target: Target::ChangeId(c.change_id.clone()),
+            attrs: Default::default(),
+        });
+        let mut draft = format!(
+            "\n<!-- TODO: rewrite this draft taken from the commit message -->\n{}\n",
+            c.title
+        );
+        if !body.is_empty() {
+            draft += &format!("\n{body}\n");
         }
-        lines_updated.push(line.clone());
+        draft += &format!("\n{marker}\n");
+        insertions.entry(place).or_default().push(Draft {
+            order,
+            change_id: c.change_id.clone(),
+            text: draft,
+        });
+    }
+    let mut inserted = Vec::new();
+    // from the end so that the block indexes stay valid.
+    for ((file, index), drafts) in insertions.into_iter().rev() {
+        let file = &mut book.files_mut()[file];
+        let text: String = drafts.iter().map(|d| d.text.as_str()).collect();
+        file.document_mut().insert(index, &text);
+        let order = drafts[0].order;
+        let ids: Vec<String> = drafts.into_iter().map(|d| d.change_id).collect();
+        let diagnostic = Diagnostic::new(
+            DiagnosticKind::InsertedMarker,
+            format!("inserted markers for {}", ids.join(", ")),
+        );
+        inserted.push((order, diagnostic.in_file(&file.name(), None)));
     }
-    Ok(lines_updated)
+    inserted.sort_by_key(|(order, _)| *order);
+    diagnostics.extend(inserted.into_iter().map(|(_, d)| d));
+    Ok(diagnostics)
 }
 
-fn remove_generated_lines(lines: Vec<String>) -> Result<Vec<String>> {
-    let mut lines_updated: Vec<String> = Vec::new();
-    let mut lines_pending: Vec<String> = Vec::new();
-    let mut end_marker_for_pending: Option<String> = None;
-    for line in lines {
-        if line.starts_with("<!--") && line.contains("ajimi::code change_id") {
-            if end_marker_for_pending.is_some() {
-                // ajimi::code appeared again without ajimi::end.
-                // push all pending lines.
-                lines_updated.append(&mut lines_pending);
-            }
-            lines_updated.push(line.clone()); // first line is kept always.
-            end_marker_for_pending = Some(line.replace("ajimi::code", "ajimi::end").to_string());
-            continue;
-        } else if Some(line.clone()) == end_marker_for_pending {
-            // end marker found. drop all pending lines.
-            lines_pending.clear();
-            end_marker_for_pending = None;
+/// Regenerates the code in `doc`, which was read from `path`. Returns the
+/// markers that could not be fixed.
+pub(crate) fn fix_document<T: CommitResolver>(
+    repo: &T,
+    path: &str,
+    doc: &mut Document,
+    options: &FixOptions,
+) -> Result<Vec<Diagnostic>> {
+    if let Some(e) = doc.errors().first() {
+        return Err(Error::Parse {
+            file: path.to_string(),
+            line: e.line,
+            message: format!("column {}: {}", e.column, e.message),
+        });
+    }
+    let mut diagnostics = replace_commit_id_with_change_id(repo, path, doc);
+    check_edited_regions(path, doc, options.force)?;
+    diagnostics.extend(insert_commit_diff_with_change_id(repo, path, doc, options)?);
+    diagnostics.extend(insert_command_output(repo, path, doc, &options.run)?);
+    Ok(diagnostics)
+}
+
+/// Replaces the change_ids in markers found in `remap`. Returns a diagnostic
+/// for each replaced one.
+pub(crate) fn remap_change_ids(path: &str, doc: &mut Document, remap: &Remap) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for region in doc.all_regions_mut() {
+        let Some((old, new)) = region
+            .change_id()
+            .and_then(|id| Some((id.to_string(), remap.get(id)?)))
+        else {
             continue;
-        } else if end_marker_for_pending.is_some() {
-            lines_pending.push(line.clone());
-        } else {
-            lines_updated.push(line.clone());
-        }
+        };
+        diagnostics.push(
+            Diagnostic::new(DiagnosticKind::Remapped, format!("remapped {old} to {new}"))
+                .in_file(path, Some(region.marker.line))
+                .with_change_id(new),
+        );
+        region.set_directive(CodeDirective {
+            target: Target::ChangeId(new.clone()),
+            attrs: region.directive.attrs.clone(),
+        });
     }
-    // tail case for non-terminated block
-    lines_updated.append(&mut lines_pending);
-    Ok(lines_updated)
+    diagnostics
 }
 
-fn format_patch<T: CommitResolver>(
-    input: &str,
+fn replace_commit_id_with_change_id<T: CommitResolver>(
     commit_resolver: &T,
-    commit_id: Option<&str>,
-) -> Result<String> {
-    let mut output = String::new();
-    let parts = input.split("\n").collect::<Vec<&str>>();
-    let parts: Vec<String> = parts
-        .chunk_by(|_, b| !b.starts_with("diff --git"))
-        .map(|a| a.join("\n"))
-        .collect();
-    for part in parts {
-        if part.trim().is_empty() {
+    path: &str,
+    doc: &mut Document,
+) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for region in doc.regions_mut() {
+        let Some(commit) = region.commit() else {
             continue;
-        }
-        if !part.starts_with("diff --git") {
-            return Err(anyhow!("format_patch: Invalid part found: {:?}", part));
-        }
-        let lines: Vec<&str> = part.split_inclusive("\n").collect();
-        let filename = lines[0]
-            .split(" ")
-            .nth(2)
-            .unwrap()
-            .strip_prefix("a/")
-            .unwrap();
-        let lang = if filename.ends_with(".rs") {
-            "rust,noplayground"
-        } else if filename.ends_with(".gitignore") || filename.ends_with(".lock") {
-            "gitconfig"
-        } else if filename.ends_with(".toml") {
-            "toml"
-        } else if filename.ends_with(".sh") {
-            "bash_script_file"
-        } else {
-            return Err(anyhow!("file type unknown for {filename}"));
         };
-        output += format!("\n```{lang}\n").as_str();
-        output += format!("(注:{filename})\n").as_str();
-        let hunks = lines.iter().fold(Vec::new(), |mut acc, line| {
-            if line.starts_with("diff --git")
-                || line.starts_with("new file")
-                || line.starts_with("---")
-                || line.starts_with("+++")
-                || line.starts_with("index ")
-            {
-                return acc;
-            }
-            if line.starts_with("@@") {
-                acc.push(Vec::new())
-            };
-            acc.last_mut().unwrap().push(line.to_string());
-            acc
-        });
-        let mut num_diff_lines = 0;
-        let mut context_marker_appeared = HashSet::new();
-        for lines in hunks {
-            for line in &lines {
-                if line.starts_with("@@ ") {
-                    if num_diff_lines > 0 && !line.starts_with("@@ -1,") {
-                        output += "\n// << 中略 >>\n\n";
-                    }
-                    let context = line
-                        .split_once("@@")
-                        .unwrap_or_default()
-                        .1
-                        .split_once("@@")
-                        .unwrap_or_default();
-                    let lineinfo = context
-                        .0
-                        .trim()
-                        .split_once(' ')
-                        .unwrap_or_default()
-                        .1
-                        .split_once(',')
-                        .unwrap_or_default()
-                        .0;
-                    let afterlinestart: usize = lineinfo.parse().unwrap_or_default();
-                    let context = context.1.strip_prefix(' ').unwrap_or_default().trim_end();
-                    if context.ends_with("{") {
-                        let line_before_hunk = commit_id
-                            .map(|commit_id| {
-                                commit_resolver
-                                    .line_from_commit(
-                                        commit_id,
-                                        filename,
-                                        afterlinestart.saturating_sub(1),
-                                    )
-                                    .unwrap_or_default()
-                            })
-                            .unwrap_or_default();
-                        if lines
-                            .iter()
-                            .find(|line| !line.starts_with("@@ ") && line.len() > 0)
-                            .unwrap()
-                            .starts_with("    ")
-                        {
-                            if !context_marker_appeared.contains(&context.to_string()) {
-                                output += context;
-                                context_marker_appeared.insert(context.to_string());
-                                output += "\n";
-                                let line_before_hunk = line_before_hunk.trim_end();
-                                if context != line_before_hunk {
-                                    output += "    // << 中略 >>\n";
-                                }
-                            }
-                        }
-                    }
-                    continue;
-                }
-                let diff_type = line.chars().nth(0).unwrap();
-                let line = &line[1..].trim_end_matches('\n');
-                if line.is_empty() {
-                    // empty line changed. just print the new line.
-                    output += "\n";
-                    continue;
-                }
-                if line.starts_with("fn ") {
-                    context_marker_appeared.insert(line.to_string());
-                }
-                let pre = match diff_type {
-                    '+' => "**",
-                    '-' => "~~",
-                    ' ' => "",
-                    c => todo!("diff_type = {c} is not supported yet. original chunk:\n{part}"),
-                };
-                let post = pre;
-                output += pre;
-                output += line;
-                output += post;
-                output += "\n";
-                num_diff_lines += 1;
-            }
+        match commit_resolver.change_id_from_commit_id(commit) {
+            Ok(change_id) => region.set_directive(CodeDirective {
+                target: Target::ChangeId(change_id),
+                attrs: region.directive.attrs.clone(),
+            }),
+            Err(e) => diagnostics.push(
+                Diagnostic::new(
+                    DiagnosticKind::UnknownCommit,
+                    format!("invalid commit: {e}"),
+                )
+                .in_file(path, Some(region.marker.line)),
+            ),
         }
-        output += "```\n";
     }
-    Ok(output)
+    diagnostics
+}
+
+fn check_edited_regions(path: &str, doc: &Document, force: bool) -> Result<()> {
+    let edited: Vec<String> = doc
+        .all_regions()
+        .filter(|region| region.is_edited())
+        .map(|region| {
+            format!(
+                "line {}: change_id {}",
+                region.marker.line,
+                region.change_id().unwrap_or_default()
+            )
+        })
+        .collect();
+    if !force && !edited.is_empty() {
+        return Err(Error::EditedRegions {
+            file: path.to_string(),
+            regions: edited,
+        });
+    }
+    Ok(())
 }
 
 fn insert_commit_diff_with_change_id<T: CommitResolver>(
     commit_resolver: &T,
-    lines: Vec<String>,
-) -> Result<Vec<String>> {
-    let mut lines_updated: Vec<String> = Vec::new();
-    for line in lines {
-        if line.contains("ajimi::code") && line.starts_with("<!--") {
-            if let Some(change_id) = line
-                .clone()
-                .split(' ')
-                .skip_while(|s| *s != "change_id")
-                .nth(1)
-            {
-                if let Ok(patch) = commit_resolver.patch_from_change_id(change_id) {
-                    lines_updated.push(line);
-                    let patch: Vec<String> =
-                        patch.trim().split('\n').map(|s| s.to_string()).collect();
-                    let (hash, title) = patch
-                        .first()
-                        .map(|s| {
-                            s.split_once(": ")
-                                .context(anyhow!(
-                                    "Should be : right after the short commit hash: {change_id} : {s}"
-                                ))
-                                .unwrap().to_owned()
-                        })
-                        .unwrap_or_default();
-                    let patch = &patch[1..];
-                    let patch = patch.join("\n");
-                    let meta_commit_info = format!("<!-- ajimi::meta::title \"{title}\" -->");
-                    lines_updated.push(meta_commit_info);
-                    lines_updated.push(format_patch(&patch, commit_resolver, Some(hash))?);
-                    let end_marker = format!("<!-- ajimi::end change_id {change_id} -->");
-                    lines_updated.push(end_marker);
-                }
+    path: &str,
+    doc: &mut Document,
+    options: &FixOptions,
+) -> Result<Vec<Diagnostic>> {
+    let mut diagnostics = Vec::new();
+    for region in doc.regions_mut() {
+        let Some(change_id) = region.change_id().map(|s| s.to_string()) else {
+            continue;
+        };
+        let diff = options
+            .diff
+            .with_attrs(&region.directive.attrs)
+            .map_err(|message| Error::Parse {
+                file: path.to_string(),
+                line: region.marker.line,
+                message,
+            })?;
+        let commit = match commit_resolver.patch_from_change_id(&change_id, &diff) {
+            Ok(commit) => commit,
+            Err(e) => {
+                diagnostics.push(
+                    Diagnostic::new(
+                        DiagnosticKind::UnknownChangeId,
+                        format!("failed to get a patch: {e}"),
+                    )
+                    .in_file(path, Some(region.marker.line))
+                    .with_change_id(&change_id),
+                );
                 continue;
             }
+        };
+        let meta_commit_info = Directive::meta("title", &commit.title);
+        let mut body = String::new();
+        if let Some(style) = region.directive.attrs.get("body") {
+            if !commit.body.is_empty() {
+                body += &format!("\n{}", render_body(&commit.body, style));
+            }
         }
-        lines_updated.push(line.clone());
+        body += &format!(
+            "{}\n",
+            render_patch(commit_resolver, &commit, &options.render)?
+        );
+        let end_marker = Directive::End {
+            change_id,
+            hash: Some(generated_hash(&format!("{meta_commit_info}\n{body}"))),
+        };
+        region.set_generated(&[meta_commit_info], &body, &end_marker);
     }
-    Ok(lines_updated)
+    Ok(diagnostics)
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use anyhow::anyhow;
-    use std::collections::HashMap;
-
-    struct MockRepo {
-        commits: HashMap<String, String>,
-    }
-    impl MockRepo {
-        fn new(commits: HashMap<String, String>) -> Self {
-            Self { commits }
+/// Runs the commands of `ajimi::run` markers at their commits and puts the
+/// output in a `txt` code block. Regions whose commit and command have not
+/// changed are kept as is. Commands that can not be run, or are not to be run
+/// as `options.enabled` is off, are reported and their regions kept as is.
+fn insert_command_output<T: CommitResolver>(
+    repo: &T,
+    path: &str,
+    doc: &mut Document,
+    options: &RunOptions,
+) -> Result<Vec<Diagnostic>> {
+    let mut diagnostics = Vec::new();
+    for region in doc.runs_mut() {
+        let Some(change_id) = region.change_id().map(|s| s.to_string()) else {
+            continue;
+        };
+        let cmd = region
+            .directive
+            .attrs
+            .get("cmd")
+            .cloned()
+            .unwrap_or_default();
+        let hash = match repo.commit_from_change_id(&change_id) {
+            Ok(commit) => commit.hash,
+            Err(e) => {
+                diagnostics.push(
+                    Diagnostic::new(
+                        DiagnosticKind::UnknownChangeId,
+                        format!("failed to get the commit: {e}"),
+                    )
+                    .in_file(path, Some(region.marker.line))
+                    .with_change_id(&change_id),
+                );
+                continue;
+            }
+        };
+        let meta = |key| region.meta(key).map(|(value, _)| value);
+        if meta("commit").as_ref() == Some(&hash)
+            && meta("cmd").as_ref() == Some(&cmd)
+            && !region.is_edited()
+        {
+            continue;
         }
-    }
-    impl CommitResolver for MockRepo {
-        fn change_id_from_commit_id(&self, commit_id: &str) -> Result<String> {
-            let stdout = self.commits.get(commit_id).context("commit not found")?;
-            if let Some(change_id) = stdout
-                .split("\n")
-                .find(|s| s.trim().starts_with("Change-Id:"))
-            {
-                change_id
-                    .split(':')
-                    .skip(1)
-                    .next()
-                    .map(|s| s.trim().to_string())
-                    .context("Change-Id line found but invalid format")
-            } else {
-                Err(anyhow!("commit found but does not have Change-Id properly"))
+        let (output, success) = match command_output(repo, &hash, &cmd, options) {
+            Ok(Some(output)) => output,
+            Ok(None) => {
+                diagnostics.push(
+                    Diagnostic::new(
+                        DiagnosticKind::StaleOutput,
+                        format!(
+                            "output of {cmd:?} is out of date; run fix with --run to update it"
+                        ),
+                    )
+                    .in_file(path, Some(region.marker.line))
+                    .with_change_id(&change_id),
+                );
+                continue;
+            }
+            Err(e) => {
+                diagnostics.push(
+                    Diagnostic::new(
+                        DiagnosticKind::CommandFailed,
+                        format!("failed to run {cmd:?} at {hash}: {e}"),
+                    )
+                    .in_file(path, Some(region.marker.line))
+                    .with_change_id(&change_id),
+                );
+                continue;
             }
+        };
+        if !success {
+            diagnostics.push(
+                Diagnostic::new(
+                    DiagnosticKind::CommandFailed,
+                    format!("{cmd} failed at {hash}; its output is embedded anyway"),
+                )
+                .in_file(path, Some(region.marker.line))
+                .with_change_id(&change_id),
+            );
         }
+        let meta = [
+            Directive::meta("commit", &hash),
+            Directive::meta("cmd", &cmd),
+        ];
+        let body = format!("\n{}\n", render_output(&output));
+        let generated: String = meta.iter().map(|m| format!("{m}\n")).collect();
+        let end_marker = Directive::End {
+            change_id,
+            hash: Some(generated_hash(&(generated + &body))),
+        };
+        region.set_generated(&meta, &body, &end_marker);
     }
+    Ok(diagnostics)
+}
 
-    #[test]
-    fn replace_commit_marker_with_change_id() {
-        let repo = MockRepo::new(HashMap::from(
-            vec![
-                (
-                    "95186358d01076804d10d840684a1325e281b292",
-                    "
-commit 95186358d01076804d10d840684a1325e281b292 (HEAD -> wasabi4b, origin/wasabi4b)
-Author: hikalium <hikalium@hikalium.com>
-Date:   Mon Sep 30 02:17:22 2024 +0900
-
-    SKIP_EXPLAIN: Add scripts/check_all_commits.sh
-    
-    Change-Id: I5471bb84313e3f50ad0a8d4aab43509ec0732fb6
-",
-                ),
-                (
-                    "9f9107d0e653eb0f185e6be012a3a9b92055c5e1",
-                    "
-commit 9f9107d0e653eb0f185e6be012a3a9b92055c5e1
-Author: hikalium <hikalium@hikalium.com>
-Date:   Sat Sep 28 11:58:52 2024 +0900
+/// Output of `cmd` at the commit `hash`, from the cache if possible, and
+/// whether it succeeded. Only the output of successful runs is cached. `None`
+/// if it is not cached and running commands is not enabled.
+fn command_output<T: CommitResolver>(
+    repo: &T,
+    hash: &str,
+    cmd: &str,
+    options: &RunOptions,
+) -> Result<Option<(String, bool)>> {
+    let cache = options.cache_dir.as_ref().map(|dir| {
+        let key = Sha256::digest(format!("{hash}\0{cmd}").as_bytes());
+        let name: String = key[..8].iter().map(|b| format!("{b:02x}")).collect();
+        dir.join(format!("{name}.txt"))
+    });
+    if let Some(output) = cache.as_ref().and_then(|p| std::fs::read_to_string(p).ok()) {
+        return Ok(Some((output, true)));
+    }
+    if !options.enabled {
+        return Ok(None);
+    }
+    let result = repo.run_at_commit(hash, cmd)?;
+    if let Some(path) = cache.as_ref().filter(|_| result.success) {
+        let dir = path.parent().unwrap_or(Path::new(""));
+        std::fs::create_dir_all(dir)
+            .and_then(|_| std::fs::write(path, &result.output))
+            .map_err(Error::io(path))?;
+    }
+    Ok(Some((result.output, result.success)))
+}
 
-    Cache glyphs in a font to speed up displaying chars
-    
-    Change-Id: Ifd40ea5f86f75f8ae4f93a0f2153c6ac73d1172b
- ",
-                ),
-                (
-                    "85fd15d0d6c8f897d2b6ee4ee06aeb2342924b95",
-                    "
-commit 85fd15d0d6c8f897d2b6ee4ee06aeb2342924b95
-Author: hikalium <hikalium@hikalium.com>
-Date:   Sat Sep 28 11:16:33 2024 +0900
+/// `output` in a `txt` code block, with a fence longer than any run of
+/// backquotes in it.
+fn render_output(output: &str) -> String {
+    let longest = output
+        .split(|c| c != '`')
+        .map(|run| run.len())
+        .max()
+        .unwrap_or(0);
+    let fence = "`".repeat(longest.max(2) + 1);
+    let newline = if output.is_empty() || output.ends_with('\n') {
+        ""
+    } else {
+        "\n"
+    };
+    format!("{fence}txt\n{output}{newline}{fence}\n")
+}
 
-    Impl hexdump
-    
-    Change-Id: I011d74fe65381a8acc75a3be5c8dad182ad1de18
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Commit;
+    use crate::InMemoryRepo;
 
+    #[test]
+    fn replace_commit_marker_with_change_id() {
+        let repo = InMemoryRepo::builder()
+            .commit("SKIP_EXPLAIN: Add scripts/check_all_commits.sh", |c| {
+                c.hash("95186358d01076804d10d840684a1325e281b292")
+                    .change_id("I5471bb84313e3f50ad0a8d4aab43509ec0732fb6")
+            })
+            .commit("Cache glyphs in a font to speed up displaying chars", |c| {
+                c.hash("9f9107d0e653eb0f185e6be012a3a9b92055c5e1")
+                    .change_id("Ifd40ea5f86f75f8ae4f93a0f2153c6ac73d1172b")
+            })
+            .commit("Impl hexdump", |c| {
+                c.hash("85fd15d0d6c8f897d2b6ee4ee06aeb2342924b95")
+                    .change_id("I011d74fe65381a8acc75a3be5c8dad182ad1de18")
+            })
+            .build();
 
- ",
-                ),
-            ]
-            .into_iter()
-            .map(|e| (e.0.to_string(), e.1.to_string()))
-            .collect::<HashMap<String, String>>(),
-        ));
+        let replace = |s: &str| {
+            let mut doc = Document::parse(s);
+            let diagnostics = replace_commit_id_with_change_id(&repo, "book.md", &mut doc);
+            assert_eq!(diagnostics.is_empty(), !s.contains("broken"));
+            doc.to_string()
+        };
 
         // if there is a commit tag, replace it with change-id.
         assert_eq!(
-            replace_commit_id_with_change_id(
-                &repo,
-                vec!["<!-- ajimi::code commit 85fd15d0d6c8f897d2b6ee4ee06aeb2342924b95 -->"]
-                    .into_iter()
-                    .map(|s| s.to_string())
-                    .collect()
-            )
-            .unwrap(),
-            vec!["<!-- ajimi::code change_id I011d74fe65381a8acc75a3be5c8dad182ad1de18 -->"]
+            replace("<!-- ajimi::code commit 85fd15d0d6c8f897d2b6ee4ee06aeb2342924b95 -->"),
+            "<!-- ajimi::code change_id I011d74fe65381a8acc75a3be5c8dad182ad1de18 -->"
         );
 
         // if there is an invalid commit tag, keep the line as is.
         assert_eq!(
-            replace_commit_id_with_change_id(
-                &repo,
-                vec!["<!-- ajimi::code commit broken_commit_id -->"]
-                    .into_iter()
-                    .map(|s| s.to_string())
-                    .collect()
-            )
-            .unwrap(),
-            vec!["<!-- ajimi::code commit broken_commit_id -->"]
+            replace("<!-- ajimi::code commit broken_commit_id -->"),
+            "<!-- ajimi::code commit broken_commit_id -->"
         );
 
         // if there is a change_id tag, do not modify it.
         assert_eq!(
-            replace_commit_id_with_change_id(
-                &repo,
-                vec!["<!-- ajimi::code change_id I011d74fe65381a8acc75a3be5c8dad182ad1de18 -->"]
-                    .into_iter()
-                    .map(|s| s.to_string())
-                    .collect()
-            )
-            .unwrap(),
-            vec!["<!-- ajimi::code change_id I011d74fe65381a8acc75a3be5c8dad182ad1de18 -->"]
+            replace("<!-- ajimi::code change_id I011d74fe65381a8acc75a3be5c8dad182ad1de18 -->"),
+            "<!-- ajimi::code change_id I011d74fe65381a8acc75a3be5c8dad182ad1de18 -->"
         );
     }
 
     #[test]
-    fn format_patch_samples() {
-        let repo = MockRepo::new(HashMap::new());
-        let repo = &repo;
-        assert_eq!(format_patch("", repo, None).unwrap(), "");
-        assert!(format_patch("aaa", repo, None).is_err());
-        assert_eq!(
-            format_patch(
-                r#"
-diff --git a/src/main.rs b/src/main.rs
-index e7a11a9..2c7001e 100644
---- a/src/main.rs
-+++ b/src/main.rs
-@@ -1,3 +1,5 @@
- fn main() {
-     println!("Hello, world!");
-+    #[allow(clippy::empty_loop)]
-+    loop {}
- }
-"#,
-                repo,
-                None
-            )
-            .unwrap(),
-            r#"
+    fn fix_inserts_generated_code() {
+        let repo = InMemoryRepo::builder()
+            .commit("Init", |c| {
+                c.change_id("I1").write("main.rs", "fn main() {}\n")
+            })
+            .build();
+        let mut doc = Document::parse("<!-- ajimi::code change_id I1 -->\n");
+        fix_document(&repo, "book.md", &mut doc, &FixOptions::default()).unwrap();
+        let fixed = doc.to_string();
+        assert!(fixed.starts_with(
+            "<!-- ajimi::code change_id I1 -->
+<!-- ajimi::meta::title \"Init\" -->
+
 ```rust,noplayground
-(注:src/main.rs)
-fn main() {
-    println!("Hello, world!");
-**    #[allow(clippy::empty_loop)]**
-**    loop {}**
-}
+(注:main.rs)
+**fn main() {}**
+```
+
+<!-- ajimi::end change_id I1 hash="
+        ));
+        // fixing again changes nothing.
+        fix_document(&repo, "book.md", &mut doc, &FixOptions::default()).unwrap();
+        assert_eq!(doc.to_string(), fixed);
+    }
+
+    #[test]
+    fn fix_inserts_command_output() {
+        let repo = InMemoryRepo::builder()
+            .commit("Init", |c| c.change_id("I1").write("src/msg", "hello\n"))
+            .commit("Change", |c| c.change_id("I2").write("src/msg", "bye\n"))
+            .build();
+        let source = "<!-- ajimi::run change_id I1 cmd=\"cat src/msg\" -->\n";
+        let mut doc = Document::parse(source);
+        // commands are not run unless enabled.
+        let diagnostics = fix_document(&repo, "book.md", &mut doc, &FixOptions::default()).unwrap();
+        assert_eq!(doc.to_string(), source);
+        assert_eq!(diagnostics[0].kind, DiagnosticKind::StaleOutput);
+        let options = FixOptions {
+            run: RunOptions {
+                enabled: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        fix_document(&repo, "book.md", &mut doc, &options).unwrap();
+        let fixed = doc.to_string();
+        assert!(fixed.starts_with(&format!(
+            "<!-- ajimi::run change_id I1 cmd=\"cat src/msg\" -->
+<!-- ajimi::meta::commit \"{}\" -->
+<!-- ajimi::meta::cmd \"cat src/msg\" -->
+
+```txt
+hello
 ```
-"#
+
+<!-- ajimi::end change_id I1 hash=",
+            repo.hashes()[0]
+        )));
+        // code markers do not see run markers.
+        assert_eq!(doc.regions().count(), 0);
+
+        // a command that can not be run is reported rather than failing fix.
+        struct NoCheckout(InMemoryRepo);
+        impl CommitResolver for NoCheckout {
+            fn change_id_from_commit_id(&self, commit_id: &str) -> Result<String> {
+                self.0.change_id_from_commit_id(commit_id)
+            }
+            fn commit_from_change_id(&self, change_id: &str) -> Result<Commit> {
+                self.0.commit_from_change_id(change_id)
+            }
+        }
+        let mut doc = Document::parse(source);
+        let diagnostics = fix_document(&NoCheckout(repo), "book.md", &mut doc, &options).unwrap();
+        assert_eq!(doc.to_string(), source);
+        assert_eq!(diagnostics[0].kind, DiagnosticKind::CommandFailed);
+        assert_eq!(render_output("a ``` b"), "````txt\na ``` b\n````\n");
+    }
+
+    #[test]
+    fn fix_renders_commit_body() {
+        let repo = InMemoryRepo::builder()
+            .commit("Init", |c| {
+                c.body("Start small.")
+                    .change_id("I1")
+                    .write("main.rs", "fn main() {}\n")
+            })
+            .build();
+        let mut doc = Document::parse("<!-- ajimi::code change_id I1 body=note -->\n");
+        fix_document(&repo, "book.md", &mut doc, &FixOptions::default()).unwrap();
+        assert!(doc.to_string().starts_with(
+            "<!-- ajimi::code change_id I1 body=note -->
+<!-- ajimi::meta::title \"Init\" -->
+
+> [!NOTE]
+> Start small.
+
+```rust,noplayground
+"
+        ));
+    }
+
+    #[test]
+    fn fix_uses_diff_options_of_marker() {
+        let repo = InMemoryRepo::builder()
+            .commit("Init", |c| c.change_id("I1").write("a.sh", "1\n2\n3\n"))
+            .commit("Change 3", |c| {
+                c.change_id("I2").write("a.sh", "1\n2\nthree\n")
+            })
+            .build();
+        let mut doc = Document::parse("<!-- ajimi::code change_id I2 context=0 -->\n");
+        fix_document(&repo, "book.md", &mut doc, &FixOptions::default()).unwrap();
+        let text = doc.to_string();
+        assert!(
+            text.contains("**three**") && !text.contains("2\n"),
+            "{text}"
         );
+        for attr in ["context=all", "algorithm=fast"] {
+            let mut doc = Document::parse(&format!("<!-- ajimi::code change_id I2 {attr} -->\n"));
+            assert!(matches!(
+                fix_document(&repo, "book.md", &mut doc, &FixOptions::default()),
+                Err(Error::Parse { line: 1, .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn edited_generated_lines_are_protected() {
+        let hash = generated_hash("<!-- ajimi::meta::title \"t\" -->\na\n");
+        let region = |body: &str| {
+            Document::parse(&format!(
+                "<!-- ajimi::code change_id I0 -->
+<!-- ajimi::meta::title \"t\" -->
+{body}
+<!-- ajimi::end change_id I0 hash {hash} -->
+"
+            ))
+        };
+        assert!(check_edited_regions("book.md", &region("a"), false).is_ok());
+        assert!(check_edited_regions("book.md", &region("b"), false).is_err());
+        assert!(check_edited_regions("book.md", &region("b"), true).is_ok());
+        // end markers without hash are accepted as is.
+        assert!(check_edited_regions(
+            "book.md",
+            &Document::parse(
+                "<!-- ajimi::code change_id I0 -->
+b
+<!-- ajimi::end change_id I0 -->
+"
+            ),
+            false
+        )
+        .is_ok());
     }
 }
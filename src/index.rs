@@ -0,0 +1,138 @@
+use crate::book::Book;
+use crate::check::book_markers;
+use crate::markdown::Outline;
+use crate::repo::CommitResolver;
+use crate::repo::GitRepo;
+use crate::Result;
+use argh::FromArgs;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Print where each commit is explained in the book
+#[argh(subcommand, name = "index")]
+pub struct Args {
+    /// git repo for commits
+    #[argh(option)]
+    code: PathBuf,
+    /// print CSV instead of JSON
+    #[argh(switch)]
+    csv: bool,
+    /// markdown files of the book
+    #[argh(positional)]
+    files: Vec<String>,
+}
+impl Args {
+    pub fn run(&self) -> Result<()> {
+        let repo = GitRepo::new(self.code.clone());
+        let book = Book::load(&self.files)?;
+        let entries = index(&book, &repo)?;
+        if self.csv {
+            print!("{}", to_csv(&entries));
+        } else {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&entries).expect("an index is always serializable")
+            );
+        }
+        Ok(())
+    }
+}
+
+/// The place in the book where a commit is explained.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Entry {
+    pub change_id: String,
+    /// `None` if the change_id is not in the repo.
+    pub hash: Option<String>,
+    pub file: PathBuf,
+    /// line of the `ajimi::code` marker
+    pub line: usize,
+    /// the closest heading above the marker
+    pub heading: Option<String>,
+    /// the anchor of `heading`, as mdBook generates it
+    pub anchor: Option<String>,
+}
+
+/// Returns an entry for each `ajimi::code` marker with a change_id, in book
+/// order.
+pub fn index<T: CommitResolver>(book: &Book, repo: &T) -> Result<Vec<Entry>> {
+    let commits = repo.all_commit_summary_in_tree()?;
+    let hashes: HashMap<&str, &str> = commits
+        .iter()
+        .map(|c| (c.change_id.as_str(), c.hash.as_str()))
+        .collect();
+    let outlines: Vec<Outline> = book
+        .files()
+        .iter()
+        .map(|f| Outline::parse(&f.document().to_string()))
+        .collect();
+    let anchors: Vec<Vec<String>> = outlines.iter().map(|o| o.anchors()).collect();
+    let entries = book_markers(book, &commits)
+        .into_iter()
+        .map(|m| {
+            let heading = outlines[m.file]
+                .headings
+                .iter()
+                .rposition(|h| h.line < m.line);
+            Entry {
+                change_id: m.change_id.to_string(),
+                hash: hashes.get(m.change_id).map(|h| h.to_string()),
+                file: m.path.to_path_buf(),
+                line: m.line,
+                heading: heading.map(|i| outlines[m.file].headings[i].text.clone()),
+                anchor: heading.map(|i| anchors[m.file][i].clone()),
+            }
+        })
+        .collect();
+    Ok(entries)
+}
+
+/// Formats `entries` as CSV with a header row.
+pub fn to_csv(entries: &[Entry]) -> String {
+    let field = |s: &str| {
+        if s.contains([',', '"', '\n', '\r']) {
+            format!("\"{}\"", s.replace('"', "\"\""))
+        } else {
+            s.to_string()
+        }
+    };
+    let mut csv = String::from("change_id,hash,file,line,heading,anchor\n");
+    for e in entries {
+        let row = [
+            field(&e.change_id),
+            field(e.hash.as_deref().unwrap_or("")),
+            field(&e.file.to_string_lossy()),
+            e.line.to_string(),
+            field(e.heading.as_deref().unwrap_or("")),
+            field(e.anchor.as_deref().unwrap_or("")),
+        ];
+        csv += &row.join(",");
+        csv.push('\n');
+    }
+    csv
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn csv_quotes_fields() {
+        let entries = [Entry {
+            change_id: "I1".to_string(),
+            hash: None,
+            file: "ch01.md".into(),
+            line: 3,
+            heading: Some("Say \"hi\", then exit".to_string()),
+            anchor: Some("say-hi-then-exit".to_string()),
+        }];
+        assert_eq!(
+            to_csv(&entries),
+            "change_id,hash,file,line,heading,anchor
+I1,,ch01.md,3,\"Say \"\"hi\"\", then exit\",say-hi-then-exit
+"
+        );
+    }
+}
@@ -1,8 +1,47 @@
+pub mod blame;
+pub mod book;
 pub mod check;
+pub mod commit;
+pub mod config;
+pub mod coverage;
+pub mod directive;
+pub mod document;
+mod error;
 pub mod fix;
+pub mod index;
+pub mod markdown;
+pub mod memory;
+pub mod remap;
+pub mod render;
 pub mod repo;
+pub mod scaffold;
+pub mod skip;
+pub mod text;
+pub mod verify;
+
+pub use book::Book;
+pub use book::BookFile;
+pub use check::CheckOptions;
+pub use check::Diagnostic;
+pub use check::DiagnosticKind;
+pub use commit::Commit;
+pub use commit::FileDiff;
+pub use commit::FileStatus;
+pub use commit::Hunk;
+pub use commit::Line;
+pub use config::Config;
+pub use error::Error;
+pub use error::Result;
+pub use fix::FixOptions;
+pub use memory::InMemoryRepo;
+pub use render::render_patch;
+pub use render::RenderOptions;
+pub use repo::CommitResolver;
+pub use repo::DiffAlgorithm;
+pub use repo::DiffOptions;
+pub use repo::GitRepo;
+pub use skip::SkipRules;
 
-use anyhow::Result;
 use argh::FromArgs;
 
 #[derive(FromArgs, PartialEq, Debug)]
@@ -16,6 +55,11 @@ impl Args {
         match &self.nested {
             SubArgs::Fix(args) => args.run(),
             SubArgs::Check(args) => args.run(),
+            SubArgs::Scaffold(args) => args.run(),
+            SubArgs::Coverage(args) => args.run(),
+            SubArgs::Index(args) => args.run(),
+            SubArgs::Blame(args) => args.run(),
+            SubArgs::VerifyCommits(args) => args.run(),
         }
     }
 }
@@ -25,4 +69,9 @@ impl Args {
 pub enum SubArgs {
     Fix(crate::fix::Args),
     Check(crate::check::Args),
+    Scaffold(crate::scaffold::Args),
+    Coverage(crate::coverage::Args),
+    Index(crate::index::Args),
+    Blame(crate::blame::Args),
+    VerifyCommits(crate::verify::Args),
 }
@@ -1,7 +1,7 @@
-use anyhow::Result;
-
-fn main() -> Result<()> {
+fn main() {
     let args: ajimi::Args = argh::from_env();
-    args.run()?;
-    Ok(())
+    if let Err(e) = args.run() {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    }
 }
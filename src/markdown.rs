@@ -0,0 +1,231 @@
+use pulldown_cmark::CodeBlockKind;
+use pulldown_cmark::Event;
+use pulldown_cmark::Parser;
+use pulldown_cmark::Tag;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::ops::Range;
+
+/// A fenced code block. Line numbers are 1-based.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Fence {
+    pub line: usize,
+    /// byte range of the whole block including the fences
+    pub range: Range<usize>,
+    /// the first line of the block, e.g. "```rust"
+    pub opening: String,
+    /// info string after the opening fence, e.g. "rust,noplayground"
+    pub info: String,
+    pub is_closed: bool,
+}
+
+/// A block-level HTML comment line, where ajimi markers live.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Comment {
+    pub line: usize,
+    pub range: Range<usize>,
+    pub text: String,
+}
+
+/// An image reference, e.g. `![alt](path)`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Image {
+    pub line: usize,
+    pub range: Range<usize>,
+    /// the line containing the image
+    pub source_line: String,
+    pub has_alt: bool,
+}
+
+/// A heading, e.g. `## Add a loop`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Heading {
+    pub line: usize,
+    /// 1 for `#`, 2 for `##`, ...
+    pub level: usize,
+    /// plain text of the heading, without markup
+    pub text: String,
+}
+
+/// The parts of a Markdown document that ajimi cares about, as seen by a
+/// CommonMark parser.
+#[derive(Debug, Default)]
+pub struct Outline {
+    pub fences: Vec<Fence>,
+    pub comments: Vec<Comment>,
+    pub images: Vec<Image>,
+    pub headings: Vec<Heading>,
+}
+impl Outline {
+    pub fn parse(src: &str) -> Self {
+        let line_starts: Vec<usize> = std::iter::once(0)
+            .chain(src.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        let line_of = |offset: usize| line_starts.partition_point(|start| *start <= offset);
+        let line_text = |line: usize| {
+            let start = line_starts[line - 1];
+            let end = line_starts
+                .get(line)
+                .map(|end| end - 1)
+                .unwrap_or(src.len());
+            src[start..end].trim_end_matches('\r')
+        };
+        let mut outline = Self::default();
+        let mut in_html_block = false;
+        let mut heading: Option<Heading> = None;
+        for (event, range) in Parser::new(src).into_offset_iter() {
+            match event {
+                Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))) => {
+                    let line = line_of(range.start);
+                    let opening = line_text(line).to_string();
+                    let fence = opening.trim_start();
+                    let fence_char = fence.chars().next().unwrap_or('`');
+                    let fence_len = fence.chars().take_while(|c| *c == fence_char).count();
+                    let closing = src[range.clone()].trim_end().lines().last().unwrap_or("");
+                    let closing = closing.trim();
+                    let is_closed = line_of(range.end.saturating_sub(1)) > line
+                        && closing.len() >= fence_len
+                        && closing.chars().all(|c| c == fence_char);
+                    outline.fences.push(Fence {
+                        line,
+                        range,
+                        opening,
+                        info: info.to_string(),
+                        is_closed,
+                    });
+                }
+                Event::Start(Tag::Heading { level, .. }) => {
+                    heading = Some(Heading {
+                        line: line_of(range.start),
+                        level: level as usize,
+                        text: String::new(),
+                    });
+                }
+                Event::Text(text) | Event::Code(text) if heading.is_some() => {
+                    heading.as_mut().unwrap().text.push_str(&text);
+                }
+                Event::End(pulldown_cmark::TagEnd::Heading(_)) => {
+                    outline.headings.extend(heading.take());
+                }
+                Event::Start(Tag::HtmlBlock) => in_html_block = true,
+                Event::End(pulldown_cmark::TagEnd::HtmlBlock) => in_html_block = false,
+                Event::Html(text) if in_html_block && text.trim_start().starts_with("<!--") => {
+                    outline.comments.push(Comment {
+                        line: line_of(range.start),
+                        range,
+                        text: text.trim_end().to_string(),
+                    });
+                }
+                Event::Start(Tag::Image { .. }) => {
+                    let line = line_of(range.start);
+                    outline.images.push(Image {
+                        line,
+                        has_alt: !src[range.clone()].starts_with("![]"),
+                        range,
+                        source_line: line_text(line).to_string(),
+                    });
+                }
+                _ => {}
+            }
+        }
+        outline
+    }
+    /// Anchors of [`Outline::headings`], made unique with a `-1`, `-2`, ...
+    /// suffix as mdBook does.
+    pub fn anchors(&self) -> Vec<String> {
+        let mut seen: HashMap<String, usize> = HashMap::new();
+        self.headings
+            .iter()
+            .map(|h| {
+                let id = slug(&h.text);
+                let count = seen.entry(id.clone()).or_default();
+                *count += 1;
+                match *count {
+                    1 => id,
+                    n => format!("{id}-{}", n - 1),
+                }
+            })
+            .collect()
+    }
+    /// 1-based line numbers of block-level HTML comments
+    pub fn comment_lines(&self) -> HashSet<usize> {
+        self.comments.iter().map(|c| c.line).collect()
+    }
+}
+
+/// The anchor mdBook gives to a heading: lowercase, alphanumerics kept,
+/// spaces turned into `-` and other characters dropped.
+pub fn slug(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.trim().chars() {
+        if c.is_alphanumeric() || c == '_' || c == '-' {
+            slug.extend(c.to_lowercase());
+        } else if c.is_whitespace() {
+            slug.push('-');
+        }
+    }
+    slug
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fences_and_comments() {
+        let outline = Outline::parse(
+            "<!-- a -->
+~~~
+<!-- not a comment -->
+~~~
+
+- list
+  ````rust
+  ```
+  ````
+
+<!--
+```
+-->
+![](x.png)
+```txt
+",
+        );
+        assert_eq!(
+            outline
+                .fences
+                .iter()
+                .map(|f| (f.line, f.info.as_str(), f.is_closed))
+                .collect::<Vec<_>>(),
+            vec![(2, "", true), (7, "rust", true), (15, "txt", false)]
+        );
+        assert_eq!(
+            outline.comments.iter().map(|c| c.line).collect::<Vec<_>>(),
+            vec![1, 11]
+        );
+        assert_eq!(outline.images.len(), 1);
+        assert_eq!(outline.images[0].line, 14);
+        assert!(!outline.images[0].has_alt);
+    }
+
+    #[test]
+    fn headings_and_slugs() {
+        let outline = Outline::parse("# Intro\n\n## Add `Vec::push` (fast)\n\n## Intro\n");
+        assert_eq!(
+            outline
+                .headings
+                .iter()
+                .map(|h| (h.line, h.level, h.text.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                (1, 1, "Intro"),
+                (3, 2, "Add Vec::push (fast)"),
+                (5, 2, "Intro")
+            ]
+        );
+        assert_eq!(
+            outline.anchors(),
+            vec!["intro", "add-vecpush-fast", "intro-1"]
+        );
+    }
+}
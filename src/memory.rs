@@ -0,0 +1,893 @@
+use crate::commit::Commit;
+use crate::commit::FileDiff;
+use crate::commit::FileStatus;
+use crate::commit::Hunk;
+use crate::commit::Line;
+use crate::repo::run_shell;
+use crate::repo::temp_dir;
+use crate::repo::CommandOutput;
+use crate::repo::CommitMetadata;
+use crate::repo::CommitResolver;
+use crate::repo::DiffAlgorithm;
+use crate::repo::DiffOptions;
+use crate::Error;
+use crate::Result;
+use sha2::Digest;
+use sha2::Sha256;
+use similar::group_diff_ops;
+use similar::Algorithm;
+use similar::DiffOp;
+use similar::DiffTag;
+use similar::TextDiff;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::ops::Range;
+use std::path::Path;
+
+/// Paths to file contents.
+type Tree = BTreeMap<String, String>;
+
+/// A repository that lives in memory, for tests of tools built on ajimi.
+///
+/// Commits are made in order on a single branch. Diffs are computed between
+/// the trees of a commit and its parent, like `git log -p` does, but with the
+/// diff algorithms of the `similar` crate: where lines can be matched in
+/// several ways, hunks may differ from the ones git makes. `minimal` diffs are
+/// made with Myers, and `histogram` diffs are not supported.
+///
+/// ```
+/// use ajimi::CommitResolver;
+/// use ajimi::InMemoryRepo;
+///
+/// let repo = InMemoryRepo::builder()
+///     .commit("Init", |c| c.change_id("I111").write("main.rs", "fn main() {}\n"))
+///     .commit("Loop", |c| {
+///         c.change_id("I222")
+///             .write("main.rs", "fn main() {\n    loop {}\n}\n")
+///     })
+///     .build();
+/// let commit = repo.commit_from_change_id("I222")?;
+/// assert_eq!(commit.title, "Loop");
+/// assert_eq!(commit.files[0].path(), "main.rs");
+/// # Ok::<(), ajimi::Error>(())
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct InMemoryRepo {
+    /// oldest first
+    commits: Vec<StoredCommit>,
+}
+
+#[derive(Clone, Debug)]
+struct StoredCommit {
+    hash: String,
+    title: String,
+    body: String,
+    author: String,
+    trailers: Vec<(String, String)>,
+    tree: Tree,
+    /// (old path, new path) of files moved in this commit
+    renames: Vec<(String, String)>,
+}
+impl StoredCommit {
+    fn change_id(&self) -> Option<&str> {
+        self.trailers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case("Change-Id"))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+impl InMemoryRepo {
+    pub fn builder() -> InMemoryRepoBuilder {
+        InMemoryRepoBuilder::default()
+    }
+    /// Hashes of all commits, oldest first.
+    pub fn hashes(&self) -> Vec<&str> {
+        self.commits.iter().map(|c| c.hash.as_str()).collect()
+    }
+    fn find(&self, commit_id: &str) -> Result<usize> {
+        let found: Vec<usize> = self
+            .commits
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| !commit_id.is_empty() && c.hash.starts_with(commit_id))
+            .map(|(i, _)| i)
+            .collect();
+        match found[..] {
+            [i] => Ok(i),
+            [] => Err(Error::not_found(format!("commit {commit_id}"))),
+            _ => Err(Error::git(format!("ambiguous commit id {commit_id}"))),
+        }
+    }
+    /// Commits with a Change-Id in `range` of indexes, newest first.
+    fn summary(&self, range: std::ops::Range<usize>) -> Vec<CommitMetadata> {
+        self.commits[range.clone()]
+            .iter()
+            .zip(range)
+            .rev()
+            .filter_map(|(c, i)| {
+                Some(CommitMetadata {
+                    hash: c.hash.clone(),
+                    title: c.title.clone(),
+                    change_id: c.change_id()?.to_string(),
+                    author: c.author.clone(),
+                    trailers: c.trailers.clone(),
+                    paths: self
+                        .commit(i, &DiffOptions::default())
+                        .files
+                        .iter()
+                        .map(|f| f.path().to_string())
+                        .collect(),
+                })
+            })
+            .collect()
+    }
+    /// The commit at `index` with the diff from its parent.
+    fn commit(&self, index: usize, options: &DiffOptions) -> Commit {
+        let c = &self.commits[index];
+        let empty = Tree::new();
+        let parent = index
+            .checked_sub(1)
+            .map(|i| &self.commits[i].tree)
+            .unwrap_or(&empty);
+        Commit {
+            hash: c.hash.clone(),
+            title: c.title.clone(),
+            body: c.body.clone(),
+            trailers: c.trailers.clone(),
+            files: diff_trees(parent, &c.tree, &c.renames, options),
+        }
+    }
+}
+
+impl CommitResolver for InMemoryRepo {
+    fn change_id_from_commit_id(&self, commit_id: &str) -> Result<String> {
+        let c = &self.commits[self.find(commit_id)?];
+        c.change_id()
+            .map(|s| s.to_string())
+            .ok_or_else(|| Error::not_found(format!("Change-Id in commit {commit_id}")))
+    }
+    fn commit_from_change_id(&self, change_id: &str) -> Result<Commit> {
+        self.patch_from_change_id(change_id, &DiffOptions::default())
+    }
+    fn patch_from_change_id(&self, change_id: &str, options: &DiffOptions) -> Result<Commit> {
+        if options.algorithm == Some(DiffAlgorithm::Histogram) {
+            return Err(Error::not_found(format!(
+                "histogram diff of {change_id} in InMemoryRepo"
+            )));
+        }
+        // the newest one wins, as `git log -1 --grep` does.
+        let index = self
+            .commits
+            .iter()
+            .rposition(|c| c.change_id() == Some(change_id))
+            .ok_or_else(|| Error::not_found(format!("commit with Change-Id {change_id}")))?;
+        Ok(self.commit(index, options))
+    }
+    fn line_from_commit(&self, commit_id: &str, file: &str, line_number: usize) -> Result<String> {
+        let not_found = || Error::not_found(format!("line {line_number} of {file} at {commit_id}"));
+        let c = &self.commits[self.find(commit_id)?];
+        let content = c.tree.get(file).ok_or_else(not_found)?;
+        line_number
+            .checked_sub(1)
+            .and_then(|i| content.split('\n').nth(i))
+            .map(|s| s.to_string())
+            .ok_or_else(not_found)
+    }
+    fn all_commit_summary_in_tree(&self) -> Result<Vec<CommitMetadata>> {
+        Ok(self.summary(0..self.commits.len()))
+    }
+    /// Writes the tree of the commit to a temporary directory and runs
+    /// `command` there.
+    fn run_at_commit(&self, commit_id: &str, command: &str) -> Result<CommandOutput> {
+        let dir = temp_dir("ajimi-memory");
+        let output = self
+            .add_checkout(&dir)
+            .and_then(|_| self.run_in_checkout(&dir, commit_id, command));
+        let _ = self.remove_checkout(&dir);
+        output
+    }
+    fn add_checkout(&self, dir: &Path) -> Result<()> {
+        std::fs::create_dir_all(dir).map_err(Error::io(dir))
+    }
+    /// Writes the tree of the commit over the checkout, removing the files of
+    /// other commits, and runs `command` there.
+    fn run_in_checkout(&self, dir: &Path, commit_id: &str, command: &str) -> Result<CommandOutput> {
+        let c = &self.commits[self.find(commit_id)?];
+        for other in &self.commits {
+            for path in other.tree.keys().filter(|p| !c.tree.contains_key(*p)) {
+                let path = dir.join(path);
+                if path.exists() {
+                    std::fs::remove_file(&path).map_err(Error::io(path))?;
+                }
+            }
+        }
+        for (path, content) in &c.tree {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap_or(dir))
+                .and_then(|_| std::fs::write(&path, content))
+                .map_err(Error::io(path))?;
+        }
+        run_shell(command, dir)
+    }
+    fn remove_checkout(&self, dir: &Path) -> Result<()> {
+        std::fs::remove_dir_all(dir).map_err(Error::io(dir))
+    }
+    fn blame_line(&self, file: &str, line_number: usize) -> Result<String> {
+        // index of the commit that wrote each line, for each file, replaying
+        // the diffs from the first commit.
+        let mut origins: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+        for i in 0..self.commits.len() {
+            for f in self.commit(i, &DiffOptions::default()).files {
+                let old = f
+                    .old_path
+                    .and_then(|p| origins.remove(&p))
+                    .unwrap_or_default();
+                let Some(new_path) = f.new_path else {
+                    continue;
+                };
+                let mut new = Vec::new();
+                let mut next = 0;
+                for h in &f.hunks {
+                    // git numbers an empty range by the line before it.
+                    let start = h.old_start - usize::from(h.old_lines > 0);
+                    new.extend_from_slice(&old[next..start]);
+                    next = start;
+                    for line in &h.lines {
+                        match line {
+                            Line::Context(_) => new.push(old[next]),
+                            Line::Added(_) => new.push(i),
+                            Line::Removed(_) => {}
+                        }
+                        next += usize::from(!matches!(line, Line::Added(_)));
+                    }
+                }
+                new.extend_from_slice(&old[next..]);
+                origins.insert(new_path, new);
+            }
+        }
+        origins
+            .get(file)
+            .and_then(|lines| lines.get(line_number.checked_sub(1)?))
+            .map(|i| self.commits[*i].hash.clone())
+            .ok_or_else(|| Error::not_found(format!("line {line_number} of {file}")))
+    }
+    fn commit_summary_in_range(&self, range: &str) -> Result<Vec<CommitMetadata>> {
+        let (from, to) = match range.split_once("..") {
+            Some((from, to)) => (Some(from).filter(|s| !s.is_empty()), to),
+            None => (None, range),
+        };
+        let start = from
+            .map(|from| self.find(from))
+            .transpose()?
+            .map_or(0, |i| i + 1);
+        let end = match to {
+            "" | "HEAD" => self.commits.len(),
+            to => self.find(to)? + 1,
+        };
+        Ok(self.summary(start..end.max(start)))
+    }
+}
+
+/// Builds an [`InMemoryRepo`] commit by commit.
+#[derive(Clone, Debug, Default)]
+pub struct InMemoryRepoBuilder {
+    repo: InMemoryRepo,
+}
+impl InMemoryRepoBuilder {
+    /// Adds a commit on top of the previous one. Its tree starts as a copy of
+    /// the parent's tree and is changed by `f`.
+    pub fn commit(mut self, title: &str, f: impl FnOnce(CommitBuilder) -> CommitBuilder) -> Self {
+        let parent = self.repo.commits.last();
+        let builder = f(CommitBuilder {
+            hash: None,
+            title: title.to_string(),
+            body: String::new(),
+            author: "A U Thor <author@example.com>".to_string(),
+            trailers: Vec::new(),
+            tree: parent.map(|c| c.tree.clone()).unwrap_or_default(),
+            renames: Vec::new(),
+        });
+        let hash = builder.hash.unwrap_or_else(|| {
+            let mut hasher = Sha256::new();
+            hasher.update(parent.map(|c| c.hash.as_str()).unwrap_or_default());
+            hasher.update(format!("{:?}", (title, &builder.body, &builder.trailers)));
+            hasher.update(format!("{:?}", builder.tree));
+            hasher.finalize()[..20]
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect()
+        });
+        self.repo.commits.push(StoredCommit {
+            hash,
+            title: builder.title,
+            body: builder.body,
+            author: builder.author,
+            trailers: builder.trailers,
+            tree: builder.tree,
+            renames: builder.renames,
+        });
+        self
+    }
+    pub fn build(self) -> InMemoryRepo {
+        self.repo
+    }
+}
+
+/// Describes a commit for [`InMemoryRepoBuilder::commit`].
+#[derive(Clone, Debug)]
+pub struct CommitBuilder {
+    hash: Option<String>,
+    title: String,
+    body: String,
+    author: String,
+    trailers: Vec<(String, String)>,
+    tree: Tree,
+    renames: Vec<(String, String)>,
+}
+impl CommitBuilder {
+    /// Uses the given hash instead of a computed one.
+    pub fn hash(mut self, hash: &str) -> Self {
+        self.hash = Some(hash.to_string());
+        self
+    }
+    pub fn body(mut self, body: &str) -> Self {
+        self.body = body.to_string();
+        self
+    }
+    /// `Name <email>`
+    pub fn author(mut self, author: &str) -> Self {
+        self.author = author.to_string();
+        self
+    }
+    pub fn trailer(mut self, key: &str, value: &str) -> Self {
+        self.trailers.push((key.to_string(), value.to_string()));
+        self
+    }
+    /// Adds a `Change-Id` trailer.
+    pub fn change_id(self, change_id: &str) -> Self {
+        self.trailer("Change-Id", change_id)
+    }
+    /// Creates or overwrites a file.
+    pub fn write(mut self, path: &str, content: &str) -> Self {
+        self.tree.insert(path.to_string(), content.to_string());
+        self
+    }
+    pub fn remove(mut self, path: &str) -> Self {
+        self.tree.remove(path);
+        self
+    }
+    /// Moves a file. The diff shows it as a rename, plus the changes made by
+    /// later calls of [`CommitBuilder::write`] on `to`.
+    pub fn rename(mut self, from: &str, to: &str) -> Self {
+        if let Some(content) = self.tree.remove(from) {
+            self.tree.insert(to.to_string(), content);
+            self.renames.push((from.to_string(), to.to_string()));
+        }
+        self
+    }
+}
+
+fn diff_trees(
+    old: &Tree,
+    new: &Tree,
+    renames: &[(String, String)],
+    options: &DiffOptions,
+) -> Vec<FileDiff> {
+    let renamed_from: BTreeSet<&str> = renames.iter().map(|(from, _)| from.as_str()).collect();
+    let renamed_to: BTreeMap<&str, &str> = renames
+        .iter()
+        .map(|(from, to)| (to.as_str(), from.as_str()))
+        .collect();
+    let paths: BTreeSet<&str> = old.keys().chain(new.keys()).map(|s| s.as_str()).collect();
+    let mut files = Vec::new();
+    for path in paths {
+        let (old_path, status) = match (old.get(path), new.get(path)) {
+            _ if renamed_from.contains(path) && !new.contains_key(path) => continue,
+            (None, Some(_)) => match renamed_to.get(path) {
+                Some(from) if old.contains_key(*from) => (Some(*from), FileStatus::Renamed),
+                _ => (None, FileStatus::Added),
+            },
+            (Some(_), None) => (Some(path), FileStatus::Deleted),
+            (Some(a), Some(b)) if a != b => (Some(path), FileStatus::Modified),
+            _ => continue,
+        };
+        let before = old_path.and_then(|p| old.get(p)).map(|s| s.as_str());
+        let after = new.get(path).map(|s| s.as_str());
+        let hunks = diff_hunks(
+            before.unwrap_or_default(),
+            after.unwrap_or_default(),
+            options,
+        );
+        // git leaves out files whose changes are all ignored.
+        if status == FileStatus::Modified && hunks.is_empty() {
+            continue;
+        }
+        files.push(FileDiff {
+            old_path: old_path.map(|s| s.to_string()),
+            new_path: after.map(|_| path.to_string()),
+            status,
+            hunks,
+        });
+    }
+    files
+}
+
+/// `text` with whitespace at line ends removed and other runs of whitespace
+/// made one space, as `git diff -b` compares lines.
+fn normalize_space(text: &str) -> String {
+    let mut normalized = String::with_capacity(text.len());
+    for line in text.split_inclusive('\n') {
+        let mut space = false;
+        for c in line.trim_end().chars() {
+            if c.is_whitespace() {
+                space = true;
+                continue;
+            }
+            if std::mem::take(&mut space) {
+                normalized.push(' ');
+            }
+            normalized.push(c);
+        }
+        if line.ends_with('\n') {
+            normalized.push('\n');
+        }
+    }
+    normalized
+}
+
+/// Lines of `text` without their `\n`, as `TextDiff::from_lines` counts them.
+fn split_lines(text: &str) -> Vec<&str> {
+    text.split_inclusive('\n')
+        .map(|l| l.strip_suffix('\n').unwrap_or(l))
+        .collect()
+}
+
+/// Hunks like `git diff` makes them, with 3 lines of context by default.
+/// Myers is used for minimal, which similar does not have. Histogram is
+/// rejected by `patch_from_change_id`.
+fn diff_hunks(old: &str, new: &str, options: &DiffOptions) -> Vec<Hunk> {
+    let algorithm = match options.algorithm {
+        Some(DiffAlgorithm::Patience) => Algorithm::Patience,
+        Some(DiffAlgorithm::Myers | DiffAlgorithm::Minimal | DiffAlgorithm::Histogram) | None => {
+            Algorithm::Myers
+        }
+    };
+    let (old_text, new_text) = if options.ignore_space_change {
+        (normalize_space(old), normalize_space(new))
+    } else {
+        (old.to_string(), new.to_string())
+    };
+    let diff = TextDiff::configure()
+        .algorithm(algorithm)
+        .diff_lines(&old_text, &new_text);
+    let (old_lines, new_lines) = (split_lines(old), split_lines(new));
+    let context = options.context.unwrap_or(3);
+    let ops = renumber(diff.ops());
+    let groups = if options.function_context {
+        widen_to_functions(&ops, &old_lines, &new_lines, context)
+    } else {
+        group_diff_ops(ops, context)
+    };
+    groups
+        .iter()
+        .filter_map(|group| {
+            let first = group.first()?;
+            let mut lines = Vec::new();
+            for op in group {
+                // lines equal but for whitespace are shown as they are now.
+                if op.tag() == DiffTag::Equal {
+                    lines.extend(
+                        op.new_range()
+                            .map(|i| Line::Context(new_lines[i].to_string())),
+                    );
+                    continue;
+                }
+                lines.extend(
+                    op.old_range()
+                        .map(|i| Line::Removed(old_lines[i].to_string())),
+                );
+                lines.extend(
+                    op.new_range()
+                        .map(|i| Line::Added(new_lines[i].to_string())),
+                );
+            }
+            let num_old = lines
+                .iter()
+                .filter(|l| !matches!(l, Line::Added(_)))
+                .count();
+            let num_new = lines
+                .iter()
+                .filter(|l| !matches!(l, Line::Removed(_)))
+                .count();
+            // git numbers an empty range by the line before it.
+            let start = |index: usize, len: usize| index + usize::from(len > 0);
+            Some(Hunk {
+                old_start: start(first.old_range().start, num_old),
+                old_lines: num_old,
+                new_start: start(first.new_range().start, num_new),
+                new_lines: num_new,
+                context: function_context(&old_lines[..first.old_range().start]),
+                lines,
+            })
+        })
+        .collect()
+}
+
+/// `ops` with their positions counted again from the start: similar can give
+/// a deletion the new position after lines inserted later.
+fn renumber(ops: &[DiffOp]) -> Vec<DiffOp> {
+    let (mut old_index, mut new_index) = (0, 0);
+    ops.iter()
+        .map(|op| {
+            let (tag, old_range, new_range) = op.as_tag_tuple();
+            let (old_len, new_len) = (old_range.len(), new_range.len());
+            let op = match tag {
+                DiffTag::Equal => DiffOp::Equal {
+                    old_index,
+                    new_index,
+                    len: old_len,
+                },
+                DiffTag::Delete => DiffOp::Delete {
+                    old_index,
+                    old_len,
+                    new_index,
+                },
+                DiffTag::Insert => DiffOp::Insert {
+                    old_index,
+                    new_index,
+                    new_len,
+                },
+                DiffTag::Replace => DiffOp::Replace {
+                    old_index,
+                    old_len,
+                    new_index,
+                    new_len,
+                },
+            };
+            old_index += old_len;
+            new_index += new_len;
+            op
+        })
+        .collect()
+}
+
+/// Whether git takes `line` for the start of a function: it starts with a
+/// letter, `_` or `$`.
+fn is_function_line(line: &str) -> bool {
+    line.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_' || c == '$')
+}
+
+/// Groups the changes of `ops` into hunks showing the whole functions around
+/// them, as `git diff -W` does: the context of each hunk is widened to the
+/// bounds of its functions, hunks that then overlap or meet are merged, and
+/// the ops are sliced to the resulting windows.
+fn widen_to_functions(
+    ops: &[DiffOp],
+    old_lines: &[&str],
+    new_lines: &[&str],
+    context: usize,
+) -> Vec<Vec<DiffOp>> {
+    let (num_old, num_new) = (old_lines.len(), new_lines.len());
+    let is_empty = |i: usize| old_lines[i].trim().is_empty();
+    // the last function line in `after..=from`.
+    let function_before = |from: usize, after: usize| {
+        let last = num_old.checked_sub(1)?;
+        (after..=from.min(last))
+            .rev()
+            .find(|&i| is_function_line(old_lines[i]))
+    };
+    // changed (old, new) line ranges, a deletion and an insertion next to
+    // each other being one change.
+    let mut changes: Vec<(Range<usize>, Range<usize>)> = Vec::new();
+    for op in ops.iter().filter(|op| op.tag() != DiffTag::Equal) {
+        match changes.last_mut() {
+            Some((old, new))
+                if old.end == op.old_range().start && new.end == op.new_range().start =>
+            {
+                old.end = op.old_range().end;
+                new.end = op.new_range().end;
+            }
+            _ => changes.push((op.old_range(), op.new_range())),
+        }
+    }
+    let mut groups = Vec::new();
+    let mut first = 0;
+    while first < changes.len() {
+        // changes close enough to share their context.
+        let mut last = first;
+        while changes
+            .get(last + 1)
+            .is_some_and(|(old, _)| old.start - changes[last].0.end <= 2 * context)
+        {
+            last += 1;
+        }
+        let (old, new) = &changes[first];
+        let mut start = (
+            old.start.saturating_sub(context),
+            new.start.saturating_sub(context),
+        );
+        // the function starts at its function line, with the comments and
+        // attributes right above it, or at the start of the file. Nothing
+        // more is needed for whole functions added at the end.
+        let appended_function =
+            old.start >= num_old && new_lines[new.start..].iter().any(|l| is_function_line(l));
+        if !appended_function {
+            let mut function_start = function_before(old.start, 0).unwrap_or(0);
+            while function_start > 0
+                && !is_empty(function_start - 1)
+                && !is_function_line(old_lines[function_start - 1])
+            {
+                function_start -= 1;
+            }
+            if function_start < start.0 {
+                start = (
+                    function_start,
+                    start.1.saturating_sub(start.0 - function_start),
+                );
+            }
+        }
+        // the function ends before the next function line and the empty lines
+        // above it, or at the end of the file. Take in the next change if it
+        // is in the context or in the same function, and look again.
+        let end = loop {
+            let (old, new) = &changes[last];
+            let after = context.min(num_old - old.end).min(num_new - new.end);
+            let mut end = (old.end + after, new.end + after);
+            let mut function_end = (old.end..num_old)
+                .find(|&i| is_function_line(old_lines[i]))
+                .unwrap_or(num_old);
+            while function_end > 0 && function_end < num_old && is_empty(function_end - 1) {
+                function_end -= 1;
+            }
+            if function_end > end.0 {
+                end = (function_end, (end.1 + function_end - end.0).min(num_new));
+            }
+            if let Some((next, _)) = changes.get(last + 1) {
+                let from = next.start.min(num_old.saturating_sub(1));
+                if from <= end.0 + context || function_before(from, end.0 + 1).is_none() {
+                    last += 1;
+                    continue;
+                }
+            }
+            break end;
+        };
+        let mut group = Vec::new();
+        let equal =
+            |group: &mut Vec<DiffOp>, old_index: usize, new_index: usize, new_end: usize| {
+                if new_index < new_end {
+                    group.push(DiffOp::Equal {
+                        old_index,
+                        new_index,
+                        len: new_end - new_index,
+                    });
+                }
+            };
+        equal(&mut group, start.0, start.1, changes[first].1.start);
+        for (i, (old, new)) in changes[first..=last].iter().enumerate() {
+            if i > 0 {
+                let (prev_old, prev_new) = &changes[first + i - 1];
+                equal(&mut group, prev_old.end, prev_new.end, new.start);
+            }
+            group.push(match (old.is_empty(), new.is_empty()) {
+                (false, true) => DiffOp::Delete {
+                    old_index: old.start,
+                    old_len: old.len(),
+                    new_index: new.start,
+                },
+                (true, false) => DiffOp::Insert {
+                    old_index: old.start,
+                    new_index: new.start,
+                    new_len: new.len(),
+                },
+                _ => DiffOp::Replace {
+                    old_index: old.start,
+                    old_len: old.len(),
+                    new_index: new.start,
+                    new_len: new.len(),
+                },
+            });
+        }
+        let (old, new) = &changes[last];
+        equal(&mut group, old.end, new.end, end.1);
+        groups.push(group);
+        first = last + 1;
+    }
+    groups
+}
+
+/// The section heading git prints after `@@`: the last function line before
+/// the hunk, cut at 80 bytes.
+fn function_context(lines_before: &[&str]) -> String {
+    let Some(line) = lines_before.iter().rev().find(|l| is_function_line(l)) else {
+        return String::new();
+    };
+    let mut end = line.len().min(80);
+    while !line.is_char_boundary(end) {
+        end -= 1;
+    }
+    line[..end].trim_end().to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn diffs_between_trees() {
+        let repo = InMemoryRepo::builder()
+            .commit("Init", |c| {
+                c.change_id("I1")
+                    .write("a.rs", "fn main() {\n    a();\n}\n")
+                    .write("b.rs", "b\n")
+            })
+            .commit("Change", |c| {
+                c.body("Some words")
+                    .change_id("I2")
+                    .write("a.rs", "fn main() {\n    b();\n}\n")
+                    .rename("b.rs", "c.rs")
+                    .write("d.rs", "d\n")
+            })
+            .commit("No Change-Id", |c| c.remove("d.rs"))
+            .build();
+        let commit = repo.commit_from_change_id("I2").unwrap();
+        assert_eq!(commit.body, "Some words");
+        assert_eq!(commit.change_id(), Some("I2"));
+        let files: Vec<(&str, FileStatus)> =
+            commit.files.iter().map(|f| (f.path(), f.status)).collect();
+        assert_eq!(
+            files,
+            vec![
+                ("a.rs", FileStatus::Modified),
+                ("c.rs", FileStatus::Renamed),
+                ("d.rs", FileStatus::Added),
+            ]
+        );
+        assert_eq!(
+            commit.files[0].hunks,
+            vec![Hunk {
+                old_start: 1,
+                old_lines: 3,
+                new_start: 1,
+                new_lines: 3,
+                context: String::new(),
+                lines: vec![
+                    Line::Context("fn main() {".to_string()),
+                    Line::Removed("    a();".to_string()),
+                    Line::Added("    b();".to_string()),
+                    Line::Context("}".to_string()),
+                ],
+            }]
+        );
+        assert_eq!(commit.files[1].old_path.as_deref(), Some("b.rs"));
+        assert!(commit.files[1].hunks.is_empty());
+        assert_eq!(
+            (
+                commit.files[2].hunks[0].old_start,
+                commit.files[2].hunks[0].new_start
+            ),
+            (0, 1)
+        );
+
+        let hashes = repo.hashes();
+        assert_eq!(
+            repo.change_id_from_commit_id(&hashes[0][..7]).unwrap(),
+            "I1"
+        );
+        assert!(repo.change_id_from_commit_id(hashes[2]).is_err());
+        assert_eq!(
+            repo.line_from_commit(hashes[1], "a.rs", 2).unwrap(),
+            "    b();"
+        );
+        assert!(repo.line_from_commit(hashes[1], "a.rs", 0).is_err());
+        assert_eq!(repo.blame_line("a.rs", 1).unwrap(), hashes[0]);
+        assert_eq!(repo.blame_line("a.rs", 2).unwrap(), hashes[1]);
+        assert_eq!(repo.blame_line("c.rs", 1).unwrap(), hashes[0]);
+        assert!(repo.blame_line("d.rs", 1).is_err());
+        let summary = repo.all_commit_summary_in_tree().unwrap();
+        let ids: Vec<&str> = summary.iter().map(|c| c.change_id.as_str()).collect();
+        assert_eq!(ids, vec!["I2", "I1"]);
+        let range = repo
+            .commit_summary_in_range(&format!("{}..", hashes[0]))
+            .unwrap();
+        let ids: Vec<&str> = range.iter().map(|c| c.change_id.as_str()).collect();
+        assert_eq!(ids, vec!["I2"]);
+    }
+
+    #[test]
+    fn hunks_with_function_context() {
+        let old =
+            "use a;\n\n// doc\nfn one() {\n    1;\n    3;\n}\n\nfn two() {\n    3;\n    4;\n}\n";
+        let new = old.replace("    3;", "    three;");
+        let options = DiffOptions {
+            context: Some(0),
+            function_context: true,
+            ..Default::default()
+        };
+        let ranges: Vec<_> = diff_hunks(old, &new, &options)
+            .iter()
+            .map(|h| (h.old_start, h.old_lines, h.context.clone()))
+            .collect();
+        assert_eq!(
+            ranges,
+            vec![
+                (3, 5, "use a;".to_string()),
+                (9, 4, "fn one() {".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn hunk_ranges_like_git() {
+        // (old, new, options, the ranges `git diff` prints for them)
+        let function_context = DiffOptions {
+            context: Some(1),
+            function_context: true,
+            ..Default::default()
+        };
+        let cases = [
+            (
+                "    x;\n    y;\nfn a() {\n    x;\n",
+                "    y;\n    y;\n\n  z\n#[test]\n",
+                DiffOptions::default(),
+                (1, 4, 1, 5),
+            ),
+            (
+                "#[test]\n// c\nfn a() {\n    x;\n\n  z\nfn b() {\n  z\n",
+                "  z\n  z\n  z\nstruct S;\n// c\n}\n",
+                function_context,
+                (1, 8, 1, 6),
+            ),
+        ];
+        for (old, new, options, ranges) in cases {
+            let hunks = diff_hunks(old, new, &options);
+            let h = &hunks[0];
+            assert_eq!(hunks.len(), 1);
+            assert_eq!((h.old_start, h.old_lines, h.new_start, h.new_lines), ranges);
+        }
+        let repo = InMemoryRepo::builder()
+            .commit("Init", |c| c.change_id("I1").write("a", "a\n"))
+            .build();
+        let histogram = DiffOptions {
+            algorithm: Some(DiffAlgorithm::Histogram),
+            ..Default::default()
+        };
+        assert!(repo.patch_from_change_id("I1", &histogram).is_err());
+    }
+
+    #[test]
+    fn ignore_space_change() {
+        let repo = InMemoryRepo::builder()
+            .commit("Init", |c| {
+                c.write("a.rs", "fn a() {\n  x();\n}\n")
+                    .write("b.rs", "fn b() {\n  y();\n}\n")
+            })
+            .commit("Reindent", |c| {
+                c.change_id("I1")
+                    .write("a.rs", "fn a() {\n    x();\n}\n")
+                    .write("b.rs", "fn b() {\n    y();\n    z();\n}\n")
+            })
+            .build();
+        let options = DiffOptions {
+            ignore_space_change: true,
+            ..Default::default()
+        };
+        let commit = repo.patch_from_change_id("I1", &options).unwrap();
+        assert_eq!(commit.files.len(), 1);
+        assert_eq!(
+            commit.files[0].hunks[0].lines,
+            vec![
+                Line::Context("fn b() {".to_string()),
+                Line::Context("    y();".to_string()),
+                Line::Added("    z();".to_string()),
+                Line::Context("}".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn function_context_like_git() {
+        assert_eq!(function_context(&["fn a() {", "    x", ""]), "fn a() {");
+        assert_eq!(function_context(&["  indented"]), "");
+    }
+}
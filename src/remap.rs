@@ -0,0 +1,205 @@
+//! Following commits whose Change-Ids changed when the history was rebuilt.
+//!
+//! `check` suggests current commits for change_ids that are no longer in the
+//! repo, and `fix --remap` rewrites markers with a mapping file written by the
+//! author:
+//!
+//! ```text
+//! # old change_id    new change_id
+//! I0123              I4567
+//! ```
+
+use crate::document::Region;
+use crate::render::render_patch;
+use crate::render::rendered_paths;
+use crate::render::RenderOptions;
+use crate::repo::CommitMetadata;
+use crate::repo::CommitResolver;
+use crate::Error;
+use crate::Result;
+use similar::TextDiff;
+use std::collections::BTreeMap;
+use std::collections::HashSet;
+use std::path::Path;
+
+/// Old change_ids to new ones.
+pub type Remap = BTreeMap<String, String>;
+
+/// Reads a mapping file with an old and a new change_id on each line. Empty
+/// lines and lines starting with `#` are ignored.
+pub fn load_remap(path: &Path) -> Result<Remap> {
+    let content = std::fs::read_to_string(path).map_err(Error::io(path))?;
+    parse_remap(&path.to_string_lossy(), &content)
+}
+
+fn parse_remap(file: &str, content: &str) -> Result<Remap> {
+    let mut remap = Remap::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |message: String| Error::Parse {
+            file: file.to_string(),
+            line: i + 1,
+            message,
+        };
+        let [old, new] = line.split_whitespace().collect::<Vec<_>>()[..] else {
+            return Err(error("expected an old and a new change_id".to_string()));
+        };
+        if remap.insert(old.to_string(), new.to_string()).is_some() {
+            return Err(error(format!("{old} is mapped twice")));
+        }
+    }
+    Ok(remap)
+}
+
+/// A commit that an unknown change_id may refer to now.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Candidate<'a> {
+    pub commit: &'a CommitMetadata,
+    /// 0.0 to 1.0
+    pub score: f32,
+}
+
+const MAX_CANDIDATES: usize = 3;
+/// Candidates whose diffs are rendered and compared with the region.
+const MAX_DIFFS: usize = 5;
+const MIN_SCORE: f32 = 0.5;
+
+fn similarity(a: &str, b: &str) -> f32 {
+    TextDiff::from_chars(a, b).ratio()
+}
+
+/// Similarity of the lines of rendered code, without the fences that all
+/// code blocks have in common.
+fn similarity_of_code(a: &str, b: &str) -> f32 {
+    let code = |text: &str| -> String {
+        text.lines()
+            .filter(|line| !line.trim().is_empty() && !line.starts_with("```"))
+            .map(|line| format!("{line}\n"))
+            .collect()
+    };
+    TextDiff::from_lines(&code(a), &code(b)).ratio()
+}
+
+/// Commits among `commits` that look like the one `region` was generated
+/// from, best first. The title is compared with `ajimi::meta::title`, and the
+/// diff with the generated code, if any.
+pub(crate) fn suggest<'a, T: CommitResolver>(
+    repo: &T,
+    region: &Region,
+    commits: &[&'a CommitMetadata],
+) -> Vec<Candidate<'a>> {
+    let title = region.meta("title").map(|(title, _)| title);
+    let body = region.body.as_ref().map_or("", |b| b.text.as_str());
+    if title.is_none() && body.trim().is_empty() {
+        return Vec::new();
+    }
+    let title_score =
+        |c: &CommitMetadata| title.as_deref().map_or(0.0, |t| similarity(t, &c.title));
+    // files touched are cheap to compare; rendering diffs needs git.
+    let paths: HashSet<&str> = rendered_paths(body).into_iter().collect();
+    let path_score = |c: &CommitMetadata| {
+        let other: HashSet<&str> = c.paths.iter().map(|p| p.as_str()).collect();
+        let union = paths.union(&other).count();
+        match union {
+            0 => 0.0,
+            union => paths.intersection(&other).count() as f32 / union as f32,
+        }
+    };
+    let mut candidates: Vec<Candidate> = commits
+        .iter()
+        .map(|c| Candidate {
+            commit: c,
+            score: match (&title, paths.is_empty()) {
+                (Some(_), true) => title_score(c),
+                (None, _) => path_score(c),
+                (Some(_), false) => (title_score(c) + path_score(c)) / 2.0,
+            },
+        })
+        .collect();
+    candidates.sort_by(|a, b| b.score.total_cmp(&a.score));
+    candidates.truncate(MAX_DIFFS);
+    if !paths.is_empty() {
+        for candidate in &mut candidates {
+            let c = candidate.commit;
+            let Ok(patch) = repo
+                .commit_from_change_id(&c.change_id)
+                .and_then(|commit| render_patch(repo, &commit, &RenderOptions::default()))
+            else {
+                continue;
+            };
+            let diff_score = similarity_of_code(body, &patch);
+            candidate.score = match &title {
+                Some(_) => (title_score(c) + diff_score) / 2.0,
+                None => diff_score,
+            };
+        }
+        candidates.sort_by(|a, b| b.score.total_cmp(&a.score));
+    }
+    candidates.retain(|c| c.score >= MIN_SCORE);
+    candidates.truncate(MAX_CANDIDATES);
+    candidates
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::document::Block;
+    use crate::document::Document;
+    use crate::InMemoryRepo;
+
+    #[test]
+    fn parse_mapping_file() {
+        let remap = parse_remap("remap.txt", "# old new\n\nI1 I11\n  I2\tI22  \n").unwrap();
+        assert_eq!(
+            remap.into_iter().collect::<Vec<_>>(),
+            vec![
+                ("I1".to_string(), "I11".to_string()),
+                ("I2".to_string(), "I22".to_string())
+            ]
+        );
+        assert!(matches!(
+            parse_remap("remap.txt", "I1\n"),
+            Err(Error::Parse { line: 1, .. })
+        ));
+        assert!(parse_remap("remap.txt", "I1 I2\nI1 I3\n").is_err());
+    }
+
+    #[test]
+    fn suggest_by_title_and_diff() {
+        let repo = InMemoryRepo::builder()
+            .commit("Add a loop", |c| {
+                c.change_id("I2")
+                    .write("main.rs", "fn main() {\n    loop {}\n}\n")
+            })
+            .commit("Add a log", |c| c.change_id("I3").write("log.rs", "log\n"))
+            .commit("Unrelated", |c| c.change_id("I4").write("x.rs", "x\n"))
+            .build();
+        let commits = repo.all_commit_summary_in_tree().unwrap();
+        let commits: Vec<&CommitMetadata> = commits.iter().collect();
+        let doc = Document::parse(
+            "<!-- ajimi::code change_id I1 -->
+<!-- ajimi::meta::title \"Add loop\" -->
+
+```rust,noplayground
+(注:main.rs)
+**fn main() {**
+**    loop {}**
+**}**
+```
+
+<!-- ajimi::end change_id I1 -->
+",
+        );
+        let Block::Region(region) = &doc.blocks()[0] else {
+            panic!("region expected");
+        };
+        let ids: Vec<&str> = suggest(&repo, region, &commits)
+            .iter()
+            .map(|c| c.commit.change_id.as_str())
+            .collect();
+        assert_eq!(ids, vec!["I2"]);
+    }
+}
@@ -0,0 +1,217 @@
+use crate::commit::Commit;
+use crate::commit::FileDiff;
+use crate::commit::Line;
+use crate::repo::CommitResolver;
+use crate::Error;
+use crate::Result;
+use std::collections::HashSet;
+
+/// How patches are rendered into the book.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RenderOptions {
+    /// Code block language for each file name suffix. The first match wins.
+    pub languages: Vec<(String, String)>,
+}
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            languages: [
+                (".rs", "rust,noplayground"),
+                (".gitignore", "gitconfig"),
+                (".lock", "gitconfig"),
+                (".toml", "toml"),
+                (".sh", "bash_script_file"),
+            ]
+            .into_iter()
+            .map(|(suffix, lang)| (suffix.to_string(), lang.to_string()))
+            .collect(),
+        }
+    }
+}
+impl RenderOptions {
+    pub fn lang_for(&self, path: &str) -> Option<&str> {
+        self.languages
+            .iter()
+            .find(|(suffix, _)| path.ends_with(suffix.as_str()))
+            .map(|(_, lang)| lang.as_str())
+    }
+}
+
+/// Starts the line naming the file at the top of each rendered code block.
+const FILE_NAME_PREFIX: &str = "(注:";
+
+/// The file names in code rendered by [`render_patch`].
+pub(crate) fn rendered_paths(rendered: &str) -> Vec<&str> {
+    rendered
+        .lines()
+        .filter_map(|line| line.strip_prefix(FILE_NAME_PREFIX)?.strip_suffix(')'))
+        .collect()
+}
+
+/// Renders the diff of a commit as code blocks, with added lines in bold and
+/// removed lines in strikethrough.
+pub fn render_patch<T: CommitResolver>(
+    commit_resolver: &T,
+    commit: &Commit,
+    options: &RenderOptions,
+) -> Result<String> {
+    let commit_id = Some(commit.hash.as_str()).filter(|hash| !hash.is_empty());
+    format_patch(&commit.files, commit_resolver, commit_id, options)
+}
+
+/// Renders a commit message body as a blockquote for `style` "quote" (or
+/// "true", as written by a bare `body` attribute), otherwise as an alert
+/// such as `> [!NOTE]` for "note".
+pub fn render_body(body: &str, style: &str) -> String {
+    let mut output = match style {
+        "true" | "quote" => String::new(),
+        alert => format!("> [!{}]\n", alert.to_uppercase()),
+    };
+    for line in body.lines() {
+        output += format!("> {line}").trim_end();
+        output += "\n";
+    }
+    output
+}
+
+fn format_patch<T: CommitResolver>(
+    files: &[FileDiff],
+    commit_resolver: &T,
+    commit_id: Option<&str>,
+    options: &RenderOptions,
+) -> Result<String> {
+    let mut output = String::new();
+    for file in files {
+        let filename = file.path();
+        let lang = options
+            .lang_for(filename)
+            .ok_or_else(|| Error::UnknownFileType {
+                path: filename.to_string(),
+            })?;
+        output += format!("\n```{lang}\n").as_str();
+        output += format!("{FILE_NAME_PREFIX}{filename})\n").as_str();
+        let mut num_diff_lines = 0;
+        let mut context_marker_appeared = HashSet::new();
+        for hunk in &file.hunks {
+            if num_diff_lines > 0 && hunk.old_start != 1 {
+                output += "\n// << 中略 >>\n\n";
+            }
+            let context = hunk.context.as_str();
+            if context.ends_with("{") {
+                let line_before_hunk = commit_id
+                    .map(|commit_id| {
+                        commit_resolver
+                            .line_from_commit(commit_id, filename, hunk.new_start.saturating_sub(1))
+                            .unwrap_or_default()
+                    })
+                    .unwrap_or_default();
+                if matches!(hunk.lines.first(), Some(Line::Context(line)) if line.starts_with("   "))
+                    && !context_marker_appeared.contains(context)
+                {
+                    output += context;
+                    context_marker_appeared.insert(context.to_string());
+                    output += "\n";
+                    let line_before_hunk = line_before_hunk.trim_end();
+                    if context != line_before_hunk {
+                        output += "    // << 中略 >>\n";
+                    }
+                }
+            }
+            for line in &hunk.lines {
+                let text = line.text();
+                if text.is_empty() {
+                    // empty line changed. just print the new line.
+                    output += "\n";
+                    continue;
+                }
+                if text.starts_with("fn ") {
+                    context_marker_appeared.insert(text.to_string());
+                }
+                let pre = match line {
+                    Line::Added(_) => "**",
+                    Line::Removed(_) => "~~",
+                    Line::Context(_) => "",
+                };
+                let post = pre;
+                output += pre;
+                output += text;
+                output += post;
+                output += "\n";
+                num_diff_lines += 1;
+            }
+        }
+        output += "```\n";
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commit::parse_diff;
+
+    struct NoRepo;
+    impl CommitResolver for NoRepo {
+        fn change_id_from_commit_id(&self, commit_id: &str) -> Result<String> {
+            Err(Error::not_found(format!("commit {commit_id}")))
+        }
+    }
+
+    #[test]
+    fn format_patch_samples() {
+        let repo = &NoRepo;
+        let format_patch = |input: &str, repo: &NoRepo, commit_id| {
+            parse_diff(input)
+                .and_then(|files| format_patch(&files, repo, commit_id, &RenderOptions::default()))
+        };
+        assert_eq!(format_patch("", repo, None).unwrap(), "");
+        assert!(format_patch("aaa", repo, None).is_err());
+        assert!(matches!(
+            format_patch("diff --git broken", repo, None),
+            Err(Error::InvalidPatch { .. })
+        ));
+        assert!(matches!(
+            format_patch("diff --git a/x.md b/x.md\n", repo, None),
+            Err(Error::UnknownFileType { .. })
+        ));
+        assert!(matches!(
+            format_patch("diff --git a/x.rs b/x.rs\n@@ -1 +1 @@\n?x\n", repo, None),
+            Err(Error::InvalidPatch { .. })
+        ));
+        assert_eq!(
+            format_patch(
+                r#"
+diff --git a/src/main.rs b/src/main.rs
+index e7a11a9..2c7001e 100644
+--- a/src/main.rs
++++ b/src/main.rs
+@@ -1,3 +1,5 @@
+ fn main() {
+     println!("Hello, world!");
++    #[allow(clippy::empty_loop)]
++    loop {}
+ }
+"#,
+                repo,
+                None
+            )
+            .unwrap(),
+            r#"
+```rust,noplayground
+(注:src/main.rs)
+fn main() {
+    println!("Hello, world!");
+**    #[allow(clippy::empty_loop)]**
+**    loop {}**
+}
+```
+"#
+        );
+    }
+
+    #[test]
+    fn render_body_styles() {
+        assert_eq!(render_body("Why.\n\nMore.", "true"), "> Why.\n>\n> More.\n");
+        assert_eq!(render_body("Why.", "note"), "> [!NOTE]\n> Why.\n");
+    }
+}
@@ -1,13 +1,144 @@
-use anyhow::anyhow;
-use anyhow::Context;
-use anyhow::Result;
+use crate::commit::parse_message;
+use crate::commit::Commit;
+use crate::directive::Attrs;
+use crate::Error;
+use crate::Result;
+use serde::Deserialize;
+use std::path::Path;
 use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
 pub struct CommitMetadata {
     pub hash: String,
     pub title: String,
     pub change_id: String,
+    /// `Name <email>`
+    pub author: String,
+    pub trailers: Vec<(String, String)>,
+    /// files added, changed or removed by the commit
+    pub paths: Vec<String>,
+}
+
+/// What a command printed, and whether it succeeded.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CommandOutput {
+    pub success: bool,
+    /// stdout followed by stderr
+    pub output: String,
+}
+
+/// The algorithms of `git diff --diff-algorithm`.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffAlgorithm {
+    Myers,
+    Minimal,
+    Patience,
+    Histogram,
+}
+impl DiffAlgorithm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Myers => "myers",
+            Self::Minimal => "minimal",
+            Self::Patience => "patience",
+            Self::Histogram => "histogram",
+        }
+    }
+}
+impl FromStr for DiffAlgorithm {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        [Self::Myers, Self::Minimal, Self::Patience, Self::Histogram]
+            .into_iter()
+            .find(|a| a.as_str() == s)
+            .ok_or_else(|| {
+                format!(
+                    "unknown diff algorithm {s:?}, expected myers, minimal, patience or histogram"
+                )
+            })
+    }
+}
+
+/// How the diff of a commit is made. Set in `ajimi.toml`, and per marker with
+/// the `context=N`, `function-context`, `algorithm=NAME` and
+/// `ignore-space-change` attributes.
+///
+/// ```toml
+/// [diff]
+/// context = 5
+/// function_context = true
+/// algorithm = "histogram"
+/// ignore_space_change = true
+/// ```
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiffOptions {
+    /// lines of context around changes, like `git diff -U` (default: 3)
+    pub context: Option<usize>,
+    /// show the whole function around changes, like `git diff -W`
+    pub function_context: bool,
+    /// like `git diff --diff-algorithm` (default: git's own setting)
+    pub algorithm: Option<DiffAlgorithm>,
+    /// ignore changes in the amount of whitespace, like `git diff -b`
+    pub ignore_space_change: bool,
+}
+impl DiffOptions {
+    /// `self` overridden by the attributes of a marker.
+    pub fn with_attrs(&self, attrs: &Attrs) -> std::result::Result<Self, String> {
+        let mut options = self.clone();
+        if let Some(context) = attrs.get("context") {
+            options.context = Some(
+                context
+                    .parse()
+                    .map_err(|_| format!("context must be a number, got {context:?}"))?,
+            );
+        }
+        let switch = |key: &str, value: &mut bool| -> std::result::Result<(), String> {
+            if let Some(v) = attrs.get(key) {
+                *value = v
+                    .parse()
+                    .map_err(|_| format!("{key} must be true or false, got {v:?}"))?;
+            }
+            Ok(())
+        };
+        switch("function-context", &mut options.function_context)?;
+        switch("ignore-space-change", &mut options.ignore_space_change)?;
+        if let Some(algorithm) = attrs.get("algorithm") {
+            options.algorithm = Some(algorithm.parse()?);
+        }
+        Ok(options)
+    }
+}
+
+/// Runs `command` with `sh -c` in `dir`.
+pub(crate) fn run_shell(command: &str, dir: &Path) -> Result<CommandOutput> {
+    let output = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .current_dir(dir)
+        .output()
+        .map_err(Error::io(dir))?;
+    Ok(CommandOutput {
+        success: output.status.success(),
+        output: String::from_utf8_lossy(&output.stdout).to_string()
+            + &String::from_utf8_lossy(&output.stderr),
+    })
+}
+
+/// A directory under the temp dir that is unique in this process.
+pub(crate) fn temp_dir(prefix: &str) -> PathBuf {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let n = COUNT.fetch_add(1, Ordering::SeqCst);
+    std::env::temp_dir().join(format!("{prefix}-{}-{n}", std::process::id()))
+}
+
+pub(crate) fn path_str(path: &Path) -> Result<&str> {
+    path.to_str()
+        .ok_or_else(|| Error::git(format!("non-UTF-8 path: {}", path.display())))
 }
 
 pub struct GitRepo {
@@ -18,28 +149,72 @@ impl GitRepo {
         Self { path }
     }
 }
-impl CommitResolver for GitRepo {
-    fn change_id_from_commit_id(&self, commit_id: &str) -> Result<String> {
+impl GitRepo {
+    pub(crate) fn git(&self, args: &[&str]) -> Result<String> {
         let output = std::process::Command::new("git")
-            .args([
-                "-C",
-                self.path
-                    .as_os_str()
-                    .to_str()
-                    .expect("path is not valid in utf-8"),
-                "log",
-                "-1",
-                commit_id,
-            ])
+            .arg("-C")
+            .arg(&self.path)
+            .args(args)
             .output()
-            .expect("Failed to get Change-Id from a commit");
-        let stdout =
-            String::from_utf8(output.stdout).expect("Failed to parse git output as a UTF-8 string");
-        let stderr =
-            String::from_utf8(output.stderr).expect("Failed to parse git output as a UTF-8 string");
+            .map_err(|e| Error::git(format!("failed to run git {}: {e}", args.join(" "))))?;
         if !output.status.success() {
-            Err(anyhow!("git cmd failed: {stderr}"))
-        } else if let Some(change_id) = stdout
+            return Err(Error::git(format!(
+                "git {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim_end()
+            )));
+        }
+        String::from_utf8(output.stdout)
+            .map_err(|_| Error::git(format!("git {} printed non-UTF-8 output", args.join(" "))))
+    }
+    /// Commits with a Change-Id in `git log [range]`, newest first.
+    fn log_summary(&self, range: Option<&str>) -> Result<Vec<CommitMetadata>> {
+        let mut args = vec![
+            "-c",
+            "core.quotePath=false",
+            "log",
+            "-M",
+            "--name-only",
+            "--pretty=format:%x00%H%x1f%an <%ae>%x1f%s%x1f%B%x1f",
+        ];
+        if let Some(range) = range {
+            args.extend(["--end-of-options", range]);
+        }
+        let stdout = self.git(&args)?;
+        let mut commits = Vec::new();
+        for record in stdout.split('\0').filter(|r| !r.is_empty()) {
+            let fields: Vec<&str> = record.splitn(5, '\x1f').collect();
+            let [hash, author, title, message, paths] = fields[..] else {
+                return Err(Error::git(format!("unexpected git log output: {record:?}")));
+            };
+            // commits without Change-Id can not be referred from the book.
+            let Some(change_id) = message
+                .split('\n')
+                .find_map(|line| line.strip_prefix("Change-Id:"))
+            else {
+                continue;
+            };
+            let (_, _, trailers) = parse_message(message);
+            commits.push(CommitMetadata {
+                hash: hash.trim().to_string(),
+                title: title.to_string(),
+                change_id: change_id.trim().to_string(),
+                author: author.to_string(),
+                trailers,
+                paths: paths
+                    .split('\n')
+                    .filter(|p| !p.is_empty())
+                    .map(|p| p.to_string())
+                    .collect(),
+            });
+        }
+        Ok(commits)
+    }
+}
+impl CommitResolver for GitRepo {
+    fn change_id_from_commit_id(&self, commit_id: &str) -> Result<String> {
+        let stdout = self.git(&["log", "-1", "--end-of-options", commit_id])?;
+        if let Some(change_id) = stdout
             .split("\n")
             .find(|s| s.trim().starts_with("Change-Id:"))
         {
@@ -47,142 +222,199 @@ impl CommitResolver for GitRepo {
                 .split(':')
                 .nth(1)
                 .map(|s| s.trim().to_string())
-                .context("Change-Id line found but invalid format")
+                .filter(|s| !s.is_empty())
+                .ok_or_else(|| Error::git(format!("invalid Change-Id line in {commit_id}")))
         } else {
-            Err(anyhow!("commit found but does not have Change-Id properly"))
+            Err(Error::not_found(format!("Change-Id in commit {commit_id}")))
         }
     }
-    fn patch_from_change_id(&self, change_id: &str) -> Result<String> {
-        let output = std::process::Command::new("git")
-            .args([
-                "-C",
-                self.path
-                    .as_os_str()
-                    .to_str()
-                    .expect("path is not valid in utf-8"),
-                "log",
-                "-1",
-                "-p",
-                "--grep",
-                change_id,
-                r#"--pretty=%h: %s"#,
-            ])
-            .output()
-            .expect("Failed to get a diff");
-        let stdout =
-            String::from_utf8(output.stdout).expect("Failed to parse git output as a UTF-8 string");
-        let stderr =
-            String::from_utf8(output.stderr).expect("Failed to parse git output as a UTF-8 string");
-        if !output.status.success() {
-            Err(anyhow!("git cmd failed: {stderr}"))
-        } else {
-            Ok(stdout.to_string())
+    fn commit_from_change_id(&self, change_id: &str) -> Result<Commit> {
+        self.patch_from_change_id(change_id, &DiffOptions::default())
+    }
+    fn patch_from_change_id(&self, change_id: &str, options: &DiffOptions) -> Result<Commit> {
+        let context = options.context.map(|n| format!("-U{n}"));
+        let algorithm = options
+            .algorithm
+            .map(|a| format!("--diff-algorithm={}", a.as_str()));
+        let mut args = vec![
+            "-c",
+            "core.quotePath=false",
+            "log",
+            "-1",
+            "-p",
+            "-M",
+            "--no-color",
+            "--no-ext-diff",
+        ];
+        args.extend(context.as_deref());
+        args.extend(algorithm.as_deref());
+        if options.function_context {
+            args.push("--function-context");
+        }
+        if options.ignore_space_change {
+            args.push("--ignore-space-change");
+        }
+        args.extend(["--grep", change_id, "--pretty=format:%H%n%B%x00"]);
+        let stdout = self.git(&args)?;
+        if stdout.trim().is_empty() {
+            return Err(Error::not_found(format!(
+                "commit with Change-Id {change_id}"
+            )));
         }
+        let (header, diff) = stdout
+            .split_once('\0')
+            .ok_or_else(|| Error::git(format!("unexpected git log output for {change_id}")))?;
+        let (hash, message) = header.split_once('\n').unwrap_or((header, ""));
+        Commit::parse(hash, message, diff)
     }
     fn all_commit_summary_in_tree(&self) -> Result<Vec<CommitMetadata>> {
-        let output = std::process::Command::new("git")
-            .args([
-                "-C",
-                self.path
-                    .as_os_str()
-                    .to_str()
-                    .expect("path is not valid in utf-8"),
-                "log",
-                r#"--pretty=COMMIT:%H:%s%n%B"#,
-            ])
-            .output()
-            .expect("Failed to get git log");
-        let stdout =
-            String::from_utf8(output.stdout).expect("Failed to parse git output as a UTF-8 string");
-        let stderr =
-            String::from_utf8(output.stderr).expect("Failed to parse git output as a UTF-8 string");
-        if !output.status.success() {
-            Err(anyhow!("git cmd failed: {stderr}"))
-        } else {
-            let lines: Vec<String> = stdout
-                .to_string()
-                .split("\n")
-                .filter(|e| e.starts_with("Change-Id:") || e.starts_with("COMMIT:"))
-                .map(|s| s.to_string())
-                .collect();
-            Ok(lines
-                .chunks_exact(2)
-                .map(|e| {
-                    let commit_info = &e[0];
-                    let mut it = commit_info.split(":").into_iter();
-                    let hash = it.by_ref().skip(1).next();
-                    let title = it.collect::<Vec<&str>>().join(":");
-                    let change_id_line = &e[1];
-                    CommitMetadata {
-                        hash: hash.unwrap_or_default().trim().to_string(),
-                        title,
-                        change_id: change_id_line
-                            .split(":")
-                            .last()
-                            .unwrap_or_default()
-                            .trim()
-                            .to_string(),
-                    }
-                })
-                .collect())
+        self.log_summary(None)
+    }
+    fn all_commits_in_tree(&self) -> Result<Vec<Commit>> {
+        let stdout = self.git(&[
+            "-c",
+            "core.quotePath=false",
+            "log",
+            "-p",
+            "-M",
+            "--no-color",
+            "--no-ext-diff",
+            "--pretty=format:%x00%H%n%B%x00",
+        ])?;
+        let mut records = stdout.split('\0').skip(1);
+        let mut commits = Vec::new();
+        while let Some(header) = records.next() {
+            let diff = records.next().unwrap_or_default();
+            let (hash, message) = header.split_once('\n').unwrap_or((header, ""));
+            // as in `log_summary`, commits without Change-Id are left out.
+            if message
+                .split('\n')
+                .any(|line| line.starts_with("Change-Id:"))
+            {
+                commits.push(Commit::parse(hash, message, diff)?);
+            }
         }
+        Ok(commits)
     }
-    fn line_from_commit(&self, commit_id: &str, file: &str, line_number: usize) -> Result<String> {
+    fn commit_summary_in_range(&self, range: &str) -> Result<Vec<CommitMetadata>> {
+        self.log_summary(Some(range))
+    }
+    fn blame_line(&self, file: &str, line_number: usize) -> Result<String> {
         if line_number < 1 {
-            return Err(anyhow!("line_number < 1"));
+            return Err(Error::not_found(format!("line 0 of {file}")));
         }
-        let query = format!("{commit_id}:{file}");
-        let output = std::process::Command::new("git")
-            .args([
-                "-C",
-                self.path
-                    .as_os_str()
-                    .to_str()
-                    .expect("path is not valid in utf-8"),
-                "show",
-                &query,
-            ])
-            .output()
-            .expect("Failed to get git log");
-        let stdout =
-            String::from_utf8(output.stdout).expect("Failed to parse git output as a UTF-8 string");
-        let stderr =
-            String::from_utf8(output.stderr).expect("Failed to parse git output as a UTF-8 string");
-        if !output.status.success() {
-            Err(anyhow!("git cmd failed: {stderr}"))
-        } else {
-            let lines: Vec<String> = stdout
-                .to_string()
-                .split("\n")
-                .map(|s| s.to_string())
-                .collect();
-            lines
-                .get(line_number - 1)
-                .ok_or(anyhow!("Line out of range"))
-                .cloned()
+        let stdout = self.git(&[
+            "blame",
+            "--porcelain",
+            "-L",
+            &format!("{line_number},+1"),
+            "HEAD",
+            "--",
+            file,
+        ])?;
+        stdout
+            .split_whitespace()
+            .next()
+            .map(|s| s.to_string())
+            .ok_or_else(|| Error::git(format!("unexpected git blame output for {file}")))
+    }
+    fn run_at_commit(&self, commit_id: &str, command: &str) -> Result<CommandOutput> {
+        let dir = temp_dir("ajimi-run");
+        let dir_str = path_str(&dir)?;
+        self.git(&["worktree", "add", "-q", "--detach", dir_str, commit_id])?;
+        let output = run_shell(command, &dir);
+        self.git(&["worktree", "remove", "--force", dir_str])?;
+        output
+    }
+    fn add_checkout(&self, dir: &Path) -> Result<()> {
+        self.git(&["worktree", "add", "-q", "--detach", path_str(dir)?])?;
+        Ok(())
+    }
+    fn run_in_checkout(&self, dir: &Path, commit_id: &str, command: &str) -> Result<CommandOutput> {
+        let worktree = GitRepo::new(dir.to_path_buf());
+        worktree.git(&["checkout", "-q", "--detach", "--force", commit_id])?;
+        // keep ignored files such as build outputs to build incrementally.
+        worktree.git(&["clean", "-q", "-d", "--force"])?;
+        run_shell(command, dir)
+    }
+    fn remove_checkout(&self, dir: &Path) -> Result<()> {
+        self.git(&["worktree", "remove", "--force", path_str(dir)?])?;
+        Ok(())
+    }
+    fn line_from_commit(&self, commit_id: &str, file: &str, line_number: usize) -> Result<String> {
+        if line_number < 1 {
+            return Err(Error::not_found(format!("line 0 of {file} at {commit_id}")));
         }
+        let stdout = self.git(&["show", "--end-of-options", &format!("{commit_id}:{file}")])?;
+        stdout
+            .split("\n")
+            .nth(line_number - 1)
+            .map(|s| s.to_string())
+            .ok_or_else(|| Error::not_found(format!("line {line_number} of {file} at {commit_id}")))
     }
 }
 
 pub trait CommitResolver {
     fn change_id_from_commit_id(&self, commit_id: &str) -> Result<String>;
-    fn patch_from_change_id(&self, _change_id: &str) -> Result<String> {
-        Ok("
-+SAMPLE_CHANGE
- SAMPLE_CHANGE
--SAMPLE_CHANGE
-"
-        .to_string())
-    }
-    fn line_from_commit(
-        &self,
-        _change_id: &str,
-        _file: &str,
-        _line_number: usize,
-    ) -> Result<String> {
-        unimplemented!()
+    fn commit_from_change_id(&self, change_id: &str) -> Result<Commit> {
+        Err(Error::not_found(format!(
+            "commit with Change-Id {change_id}"
+        )))
+    }
+    /// Like [`commit_from_change_id`](Self::commit_from_change_id), with the
+    /// diff made with `options`. Resolvers that can not honour them return the
+    /// default diff.
+    fn patch_from_change_id(&self, change_id: &str, _options: &DiffOptions) -> Result<Commit> {
+        self.commit_from_change_id(change_id)
+    }
+    fn line_from_commit(&self, commit_id: &str, file: &str, line_number: usize) -> Result<String> {
+        Err(Error::not_found(format!(
+            "line {line_number} of {file} at {commit_id}"
+        )))
     }
     fn all_commit_summary_in_tree(&self) -> Result<Vec<CommitMetadata>> {
-        unimplemented!()
+        Ok(Vec::new())
+    }
+    /// The commits of [`all_commit_summary_in_tree`](Self::all_commit_summary_in_tree)
+    /// with their diffs, for resolvers that can read them all at once.
+    fn all_commits_in_tree(&self) -> Result<Vec<Commit>> {
+        self.all_commit_summary_in_tree()?
+            .iter()
+            .map(|c| self.commit_from_change_id(&c.change_id))
+            .collect()
+    }
+    /// Hash of the commit that last changed line `line_number` (1-based) of
+    /// `file` in the current tree, as `git blame` finds it.
+    fn blame_line(&self, file: &str, line_number: usize) -> Result<String> {
+        Err(Error::not_found(format!(
+            "commit of line {line_number} of {file}"
+        )))
+    }
+    /// Runs `command` with `sh -c` in a checkout of `commit_id`.
+    fn run_at_commit(&self, commit_id: &str, command: &str) -> Result<CommandOutput> {
+        Err(Error::not_found(format!(
+            "checkout of {commit_id} to run {command}"
+        )))
+    }
+    /// Makes a checkout in `dir` to run commands at several commits with
+    /// [`run_in_checkout`](Self::run_in_checkout).
+    fn add_checkout(&self, dir: &Path) -> Result<()> {
+        Err(Error::not_found(format!("checkout in {}", dir.display())))
+    }
+    /// Switches the checkout in `dir` to `commit_id` and runs `command` with
+    /// `sh -c` there. Ignored files, such as build outputs, are kept.
+    fn run_in_checkout(&self, dir: &Path, commit_id: &str, command: &str) -> Result<CommandOutput> {
+        Err(Error::not_found(format!(
+            "checkout in {} to run {command} at {commit_id}",
+            dir.display()
+        )))
+    }
+    /// Removes a checkout made with [`add_checkout`](Self::add_checkout).
+    fn remove_checkout(&self, dir: &Path) -> Result<()> {
+        Err(Error::not_found(format!("checkout in {}", dir.display())))
+    }
+    /// Commits in a revision range such as `A..B` or `A..`, newest first.
+    fn commit_summary_in_range(&self, range: &str) -> Result<Vec<CommitMetadata>> {
+        Err(Error::not_found(format!("commits in {range}")))
     }
 }
@@ -0,0 +1,159 @@
+use crate::check::book_markers;
+use crate::check::Diagnostic;
+use crate::check::DiagnosticKind;
+use crate::config::Config;
+use crate::directive::CodeDirective;
+use crate::directive::Directive;
+use crate::directive::Target;
+use crate::repo::CommitMetadata;
+use crate::repo::CommitResolver;
+use crate::repo::GitRepo;
+use crate::skip::SkipRules;
+use crate::Book;
+use crate::Error;
+use crate::Result;
+use argh::FromArgs;
+use std::io::Write;
+use std::path::PathBuf;
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Write a chapter draft with a section for each commit
+#[argh(subcommand, name = "scaffold")]
+pub struct Args {
+    /// git repo for commits
+    #[argh(option)]
+    code: PathBuf,
+    /// revision range such as A..B (default: the commits after the last one
+    /// explained in the given files)
+    #[argh(option)]
+    range: Option<String>,
+    /// file to write the draft to (default: stdout). Existing files are not
+    /// overwritten.
+    #[argh(option, short = 'o')]
+    output: Option<PathBuf>,
+    /// config file (default: ajimi.toml if it exists)
+    #[argh(option)]
+    config: Option<PathBuf>,
+    /// markdown files of the book written so far
+    #[argh(positional)]
+    files: Vec<String>,
+}
+impl Args {
+    pub fn run(&self) -> Result<()> {
+        let repo = GitRepo::new(self.code.clone());
+        let skip = Config::find(self.config.as_deref())?.skip;
+        let commits = match &self.range {
+            Some(range) => repo.commit_summary_in_range(range)?,
+            None => {
+                let commits = repo.all_commit_summary_in_tree()?;
+                let book = Book::load(&self.files)?;
+                let last = book_markers(&book, &commits)
+                    .iter()
+                    .filter_map(|m| m.order)
+                    .max();
+                // newest first, so the commits after `last` are at the head.
+                let num_new = commits.len() - last.map_or(0, |order| order + 1);
+                commits[..num_new].to_vec()
+            }
+        };
+        for c in commits.iter().rev() {
+            if let Some(reason) = skip.reason(c)? {
+                eprintln!("skip: {} ({reason}): {}", c.change_id, c.title);
+            }
+        }
+        let (draft, diagnostics) = scaffold(&repo, &commits, &skip)?;
+        for diagnostic in diagnostics {
+            eprintln!("{diagnostic}");
+        }
+        match &self.output {
+            Some(path) => std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(path)
+                .and_then(|mut f| f.write_all(draft.as_bytes()))
+                .map_err(Error::io(path)),
+            None => {
+                print!("{draft}");
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Returns a Markdown draft with a heading, the message body as prose and an
+/// `ajimi::code` marker for each of `commits` (newest first) that is not
+/// skipped, oldest first, and the commits whose message could not be read.
+pub fn scaffold<T: CommitResolver>(
+    repo: &T,
+    commits: &[CommitMetadata],
+    skip: &SkipRules,
+) -> Result<(String, Vec<Diagnostic>)> {
+    let mut draft = String::from("# TODO: chapter title\n");
+    let mut diagnostics = Vec::new();
+    for c in commits.iter().rev() {
+        if skip.reason(c)?.is_some() {
+            continue;
+        }
+        let body = match repo.commit_from_change_id(&c.change_id) {
+            Ok(commit) => commit.body,
+            Err(e) => {
+                diagnostics.push(
+                    Diagnostic::new(
+                        DiagnosticKind::UnknownChangeId,
+                        format!("failed to get the commit message: {e}"),
+                    )
+                    .with_change_id(&c.change_id),
+                );
+                String::new()
+            }
+        };
+        let body = if body.is_empty() {
+            "TODO: explain this change.".to_string()
+        } else {
+            body
+        };
+        let marker = Directive::Code(CodeDirective {
+            target: Target::ChangeId(c.change_id.clone()),
+            attrs: Default::default(),
+        });
+        draft += &format!("\n## {}\n\n{body}\n\n{marker}\n", c.title);
+    }
+    Ok((draft, diagnostics))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::InMemoryRepo;
+
+    #[test]
+    fn scaffold_commits() {
+        let repo = InMemoryRepo::builder()
+            .commit("Init", |c| c.change_id("I1").write("a.rs", "a\n"))
+            .commit("SKIP_EXPLAIN: Tooling", |c| c.change_id("I2"))
+            .commit("Add b", |c| {
+                c.body("Because b is nice.")
+                    .change_id("I3")
+                    .write("b.rs", "b\n")
+            })
+            .build();
+        let commits = repo.all_commit_summary_in_tree().unwrap();
+        assert_eq!(
+            scaffold(&repo, &commits, &SkipRules::default()).unwrap().0,
+            "# TODO: chapter title
+
+## Init
+
+TODO: explain this change.
+
+<!-- ajimi::code change_id I1 -->
+
+## Add b
+
+Because b is nice.
+
+<!-- ajimi::code change_id I3 -->
+"
+        );
+    }
+}
@@ -0,0 +1,270 @@
+use crate::repo::CommitMetadata;
+use crate::Error;
+use crate::Result;
+use globset::Glob;
+use globset::GlobSet;
+use globset::GlobSetBuilder;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fmt;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Commits that do not need to be explained in the book. A commit is skipped
+/// if any of the rules matches it.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "SkipSettings")]
+pub struct SkipRules {
+    /// skip commits whose title contains one of these
+    pub title_contains: Vec<String>,
+    /// skip commits with an `Ajimi-Skip: true` trailer
+    pub trailer: bool,
+    /// skip commits by these authors, given as a name, an email or `Name <email>`
+    pub authors: Vec<String>,
+    paths: Vec<String>,
+    globs: GlobSet,
+    list: Option<PathBuf>,
+    /// the lines of `list`, once read
+    listed: Option<HashSet<String>>,
+}
+impl Default for SkipRules {
+    fn default() -> Self {
+        Self {
+            title_contains: vec!["SKIP_EXPLAIN: ".to_string()],
+            trailer: true,
+            authors: Vec::new(),
+            paths: Vec::new(),
+            globs: GlobSet::empty(),
+            list: None,
+            listed: None,
+        }
+    }
+}
+impl PartialEq for SkipRules {
+    fn eq(&self, other: &Self) -> bool {
+        // `globs` is built from `paths`
+        self.title_contains == other.title_contains
+            && self.trailer == other.trailer
+            && self.authors == other.authors
+            && self.paths == other.paths
+            && self.list == other.list
+            && self.listed == other.listed
+    }
+}
+impl Eq for SkipRules {}
+
+/// The `[skip]` section of the config, before the globs are built.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct SkipSettings {
+    title_contains: Vec<String>,
+    trailer: bool,
+    paths: Vec<String>,
+    authors: Vec<String>,
+    list: Option<PathBuf>,
+}
+impl Default for SkipSettings {
+    fn default() -> Self {
+        let rules = SkipRules::default();
+        Self {
+            title_contains: rules.title_contains,
+            trailer: rules.trailer,
+            paths: Vec::new(),
+            authors: Vec::new(),
+            list: None,
+        }
+    }
+}
+impl TryFrom<SkipSettings> for SkipRules {
+    type Error = Error;
+    fn try_from(settings: SkipSettings) -> Result<Self> {
+        let mut rules = SkipRules {
+            title_contains: settings.title_contains,
+            trailer: settings.trailer,
+            authors: settings.authors,
+            ..Default::default()
+        }
+        .with_paths(settings.paths)?;
+        // read by `Config::load`, relative to the config file
+        rules.list = settings.list;
+        Ok(rules)
+    }
+}
+
+/// Why a commit was skipped.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SkipReason {
+    Title(String),
+    Trailer,
+    Paths,
+    Author(String),
+    List(PathBuf),
+}
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::Title(s) => write!(f, "title contains {s:?}"),
+            SkipReason::Trailer => write!(f, "trailer Ajimi-Skip: true"),
+            SkipReason::Paths => write!(f, "only touches skipped paths"),
+            SkipReason::Author(s) => write!(f, "author is {s}"),
+            SkipReason::List(path) => write!(f, "listed in {}", path.display()),
+        }
+    }
+}
+
+impl SkipRules {
+    /// Also skips commits that only touch files matching `paths`, e.g.
+    /// `scripts/**`.
+    pub fn with_paths(mut self, paths: Vec<String>) -> Result<Self> {
+        let invalid = |e: globset::Error| Error::Config {
+            message: format!("invalid glob in skip.paths: {e}"),
+        };
+        let mut globs = GlobSetBuilder::new();
+        for pattern in &paths {
+            globs.add(Glob::new(pattern).map_err(invalid)?);
+        }
+        self.globs = globs.build().map_err(invalid)?;
+        self.paths = paths;
+        Ok(self)
+    }
+    /// Also skips the commits listed in the file at `path`, a Change-Id or a
+    /// commit hash on each line. Empty lines and lines starting with `#` are
+    /// ignored.
+    pub fn with_list(mut self, path: PathBuf) -> Result<Self> {
+        self.listed = Some(read_list(&path)?);
+        self.list = Some(path);
+        Ok(self)
+    }
+    /// The globs of files that commits may only touch to be skipped.
+    pub fn paths(&self) -> &[String] {
+        &self.paths
+    }
+    /// Reads the skip list, resolving a relative path against `base_dir`.
+    pub(crate) fn load_list(&mut self, base_dir: &Path) -> Result<()> {
+        if let Some(list) = &self.list {
+            self.listed = Some(read_list(&base_dir.join(list))?);
+        }
+        Ok(())
+    }
+    /// Returns the first rule that matches `commit`, if any.
+    pub fn reason(&self, commit: &CommitMetadata) -> Result<Option<SkipReason>> {
+        if let Some(s) = self
+            .title_contains
+            .iter()
+            .find(|s| commit.title.contains(s.as_str()))
+        {
+            return Ok(Some(SkipReason::Title(s.clone())));
+        }
+        if self.trailer
+            && commit.trailers.iter().any(|(k, v)| {
+                k.eq_ignore_ascii_case("Ajimi-Skip") && v.trim().eq_ignore_ascii_case("true")
+            })
+        {
+            return Ok(Some(SkipReason::Trailer));
+        }
+        if !self.paths.is_empty()
+            && !commit.paths.is_empty()
+            && commit.paths.iter().all(|p| self.globs.is_match(p))
+        {
+            return Ok(Some(SkipReason::Paths));
+        }
+        if let Some(a) = self.authors.iter().find(|a| is_author(&commit.author, a)) {
+            return Ok(Some(SkipReason::Author(a.clone())));
+        }
+        if let Some(list) = &self.list {
+            let Some(listed) = &self.listed else {
+                return Err(Error::Config {
+                    message: format!("skip list {} was not read", list.display()),
+                });
+            };
+            if listed.contains(&commit.change_id)
+                || listed
+                    .iter()
+                    .any(|h| h.len() >= 7 && commit.hash.starts_with(h.as_str()))
+            {
+                return Ok(Some(SkipReason::List(list.clone())));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Reads the Change-Ids and commit hashes of a skip list.
+fn read_list(path: &Path) -> Result<HashSet<String>> {
+    let content = std::fs::read_to_string(path).map_err(Error::io(path))?;
+    Ok(content
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.to_string())
+        .collect())
+}
+
+/// True if `author` (`Name <email>`) is `pattern`, its name or its email.
+fn is_author(author: &str, pattern: &str) -> bool {
+    let (name, email) = author
+        .split_once(" <")
+        .map(|(name, email)| (name, email.trim_end_matches('>')))
+        .unwrap_or((author, ""));
+    author == pattern || name == pattern || email == pattern
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn skip_reasons() {
+        let commit = |title: &str, trailers: &[(&str, &str)], paths: &[&str]| CommitMetadata {
+            hash: "0123456789abcdef".to_string(),
+            title: title.to_string(),
+            change_id: "I0".to_string(),
+            author: "Bot <bot@example.com>".to_string(),
+            trailers: trailers
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            paths: paths.iter().map(|p| p.to_string()).collect(),
+        };
+        let rules = SkipRules::default()
+            .with_paths(vec!["scripts/**".to_string()])
+            .unwrap();
+        let reason = |c: &CommitMetadata| rules.reason(c).unwrap();
+        assert_eq!(
+            reason(&commit("SKIP_EXPLAIN: x", &[], &[])),
+            Some(SkipReason::Title("SKIP_EXPLAIN: ".to_string()))
+        );
+        assert_eq!(
+            reason(&commit("x", &[("Ajimi-Skip", "true")], &[])),
+            Some(SkipReason::Trailer)
+        );
+        assert_eq!(
+            reason(&commit("x", &[], &["scripts/a.sh", "scripts/b/c.sh"])),
+            Some(SkipReason::Paths)
+        );
+        assert_eq!(
+            reason(&commit("x", &[], &["scripts/a.sh", "src/main.rs"])),
+            None
+        );
+
+        let mut rules = SkipRules {
+            authors: vec!["bot@example.com".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            rules.reason(&commit("x", &[], &[])).unwrap(),
+            Some(SkipReason::Author("bot@example.com".to_string()))
+        );
+        rules.authors.clear();
+        rules.list = Some(PathBuf::from("skip.txt"));
+        assert!(rules.reason(&commit("x", &[], &[])).is_err());
+        rules.listed = Some(HashSet::from(["0123456".to_string()]));
+        assert_eq!(
+            rules.reason(&commit("x", &[], &[])).unwrap(),
+            Some(SkipReason::List(PathBuf::from("skip.txt")))
+        );
+        assert!(SkipRules::default()
+            .with_paths(vec!["a/[".to_string()])
+            .is_err());
+    }
+}
@@ -0,0 +1,147 @@
+use crate::Error;
+use crate::Result;
+use similar::ChangeTag;
+use similar::TextDiff;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+const BOM: &str = "\u{feff}";
+
+/// A text file whose content is handled with `\n` line endings, remembering
+/// how it was encoded on disk so that it can be written back the same way.
+#[derive(Debug, PartialEq, Eq)]
+pub struct TextFile {
+    /// content without BOM, with `\r\n` converted to `\n`
+    pub content: String,
+    has_bom: bool,
+    /// whether most lines end with `\r\n`; used for lines added
+    is_crlf: bool,
+    /// whether each line of `content` ended with `\r\n`
+    crlf_lines: Vec<bool>,
+    has_trailing_newline: bool,
+}
+impl TextFile {
+    pub fn read(path: &Path) -> Result<Self> {
+        let bytes = fs::read(path).map_err(Error::io(path))?;
+        let s = String::from_utf8(bytes).map_err(|e| Error::Io {
+            path: path.to_path_buf(),
+            source: std::io::Error::new(std::io::ErrorKind::InvalidData, e),
+        })?;
+        Ok(Self::parse(&s))
+    }
+    pub fn parse(s: &str) -> Self {
+        let (has_bom, s) = match s.strip_prefix(BOM) {
+            Some(s) => (true, s),
+            None => (false, s),
+        };
+        let num_crlf = s.matches("\r\n").count();
+        let num_lf = s.matches('\n').count() - num_crlf;
+        Self {
+            content: s.replace("\r\n", "\n"),
+            has_bom,
+            is_crlf: num_crlf > num_lf,
+            crlf_lines: s
+                .split_inclusive('\n')
+                .map(|l| l.ends_with("\r\n"))
+                .collect(),
+            has_trailing_newline: s.ends_with('\n'),
+        }
+    }
+    /// Encodes `content` in the same way as the original file.
+    pub fn render(&self, content: &str) -> String {
+        let mut content = content.to_string();
+        if self.has_trailing_newline && !content.ends_with('\n') {
+            content.push('\n');
+        } else if !self.has_trailing_newline && content.ends_with('\n') {
+            content.pop();
+        }
+        let is_mixed = self.crlf_lines.iter().any(|&crlf| crlf != self.is_crlf);
+        let content = if !is_mixed {
+            if self.is_crlf {
+                content.replace('\n', "\r\n")
+            } else {
+                content
+            }
+        } else {
+            // lines kept from the original keep their own line ending.
+            let mut mixed = String::with_capacity(content.len());
+            for change in TextDiff::from_lines(&self.content, &content).iter_all_changes() {
+                let crlf = match change.tag() {
+                    ChangeTag::Delete => continue,
+                    ChangeTag::Equal => change.old_index().map(|i| self.crlf_lines[i]),
+                    ChangeTag::Insert => None,
+                };
+                let line = change.value();
+                match line.strip_suffix('\n') {
+                    Some(line) if crlf.unwrap_or(self.is_crlf) => {
+                        mixed += line;
+                        mixed += "\r\n";
+                    }
+                    _ => mixed += line,
+                }
+            }
+            mixed
+        };
+        if self.has_bom {
+            format!("{BOM}{content}")
+        } else {
+            content
+        }
+    }
+    /// Writes `content` to `path` via a temporary file and a rename, so that
+    /// the file is never left half-written.
+    pub fn write(&self, path: &Path, content: &str) -> Result<()> {
+        write_atomic(path, self.render(content).as_bytes())
+    }
+}
+
+pub fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| Error::Io {
+            path: path.to_path_buf(),
+            source: std::io::Error::new(std::io::ErrorKind::InvalidInput, "not a file path"),
+        })?
+        .to_string_lossy();
+    let tmp_path = path.with_file_name(format!(".{file_name}.ajimi-{}.tmp", std::process::id()));
+    let result = (|| -> Result<()> {
+        let mut file = fs::File::create(&tmp_path).map_err(Error::io(&tmp_path))?;
+        file.write_all(data).map_err(Error::io(&tmp_path))?;
+        file.sync_all().map_err(Error::io(&tmp_path))?;
+        if let Ok(metadata) = fs::metadata(path) {
+            fs::set_permissions(&tmp_path, metadata.permissions()).map_err(Error::io(&tmp_path))?;
+        }
+        fs::rename(&tmp_path, path).map_err(Error::io(path))
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip_keeps_encoding() {
+        for s in [
+            "a\nb\n",
+            "a\nb",
+            "a\r\nb\r\n",
+            "\u{feff}a\r\nb",
+            "\u{feff}",
+            "",
+            "a\r\nb\nc\r\n",
+        ] {
+            let file = TextFile::parse(s);
+            assert_eq!(file.render(&file.content), s);
+        }
+        let file = TextFile::parse("\u{feff}a\r\nb\r\n");
+        assert_eq!(file.content, "a\nb\n");
+        assert_eq!(file.render("a\nc"), "\u{feff}a\r\nc\r\n");
+        let file = TextFile::parse("a\r\nb\nc\r\n");
+        assert_eq!(file.render("a\nb\nx\nc\n"), "a\r\nb\nx\r\nc\r\n");
+    }
+}
@@ -0,0 +1,230 @@
+use crate::book::Book;
+use crate::check::book_markers;
+use crate::config::Config;
+use crate::repo::temp_dir;
+use crate::repo::CommandOutput;
+use crate::repo::CommitResolver;
+use crate::repo::GitRepo;
+use crate::Error;
+use crate::Result;
+use argh::FromArgs;
+use serde::Deserialize;
+use std::any::Any;
+use std::fmt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Run a command at each commit explained in the book
+#[argh(subcommand, name = "verify-commits")]
+pub struct Args {
+    /// git repo for commits
+    #[argh(option)]
+    code: PathBuf,
+    /// shell command to run in the checkout of each commit (default:
+    /// verify.command in the config, or "cargo build")
+    #[argh(option)]
+    command: Option<String>,
+    /// number of commits to verify at the same time (default: verify.jobs in
+    /// the config, or 1)
+    #[argh(option, short = 'j')]
+    jobs: Option<usize>,
+    /// start from the step with this change_id, e.g. the one that broke last
+    /// time
+    #[argh(option)]
+    resume_from: Option<String>,
+    /// config file (default: ajimi.toml if it exists)
+    #[argh(option)]
+    config: Option<PathBuf>,
+    /// markdown files of the book
+    #[argh(positional)]
+    files: Vec<String>,
+}
+impl Args {
+    pub fn run(&self) -> Result<()> {
+        let repo = GitRepo::new(self.code.clone());
+        let mut options = Config::find(self.config.as_deref())?.verify;
+        if let Some(command) = &self.command {
+            options.command = command.clone();
+        }
+        if let Some(jobs) = self.jobs {
+            options.jobs = jobs;
+        }
+        let book = Book::load(&self.files)?;
+        let mut steps = book_steps(&book, &repo)?;
+        if let Some(change_id) = &self.resume_from {
+            let start = steps
+                .iter()
+                .position(|s| &s.change_id == change_id)
+                .ok_or_else(|| Error::not_found(format!("change_id {change_id} in the book")))?;
+            steps.drain(..start);
+        }
+        let outcomes = verify_commits(&repo, &steps, &options)?;
+        let mut num_failed = 0;
+        for (step, outcome) in steps.iter().zip(&outcomes) {
+            if outcome.success {
+                println!("ok   {step}");
+                continue;
+            }
+            println!("FAIL {step}");
+            let lines: Vec<&str> = outcome.output.lines().collect();
+            for line in &lines[lines.len().saturating_sub(OUTPUT_TAIL)..] {
+                println!("    {line}");
+            }
+            if num_failed == 0 {
+                eprintln!("To try again from here: --resume-from {}", step.change_id);
+            }
+            num_failed += 1;
+        }
+        if num_failed > 0 {
+            return Err(Error::StepsFailed {
+                num_steps: num_failed,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Lines of output printed for a failed step.
+const OUTPUT_TAIL: usize = 20;
+
+/// Settings of `verify-commits` in `ajimi.toml`.
+///
+/// ```toml
+/// [verify]
+/// command = "cargo test"
+/// jobs = 4
+/// ```
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct VerifyOptions {
+    /// run with `sh -c` in the checkout of each commit
+    pub command: String,
+    /// number of checkouts verified at the same time
+    pub jobs: usize,
+}
+impl Default for VerifyOptions {
+    fn default() -> Self {
+        Self {
+            command: "cargo build".to_string(),
+            jobs: 1,
+        }
+    }
+}
+
+/// A commit explained in the book.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Step {
+    pub change_id: String,
+    pub hash: String,
+    pub title: String,
+    /// `path:line` of the marker
+    pub location: String,
+}
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {:?} ({})", self.change_id, self.title, self.location)
+    }
+}
+
+/// The commits explained in `book`, in book order. Markers of commits not in
+/// the repo are left out; `check` reports them.
+pub fn book_steps<T: CommitResolver>(book: &Book, repo: &T) -> Result<Vec<Step>> {
+    let commits = repo.all_commit_summary_in_tree()?;
+    Ok(book_markers(book, &commits)
+        .iter()
+        .filter_map(|m| {
+            let c = &commits[commits.len() - 1 - m.order?];
+            Some(Step {
+                change_id: c.change_id.clone(),
+                hash: c.hash.clone(),
+                title: c.title.clone(),
+                location: format!("{}:{}", m.path.display(), m.line),
+            })
+        })
+        .collect())
+}
+
+/// Runs `options.command` at each of `steps`, in checkouts of `repo` that are
+/// removed afterwards. Returns an outcome for each step.
+pub fn verify_commits<T: CommitResolver + Sync>(
+    repo: &T,
+    steps: &[Step],
+    options: &VerifyOptions,
+) -> Result<Vec<CommandOutput>> {
+    let base = temp_dir("ajimi-verify");
+    let workers: Vec<PathBuf> = (0..options.jobs.clamp(1, steps.len().max(1)))
+        .map(|i| base.join(format!("worker-{i}")))
+        .collect();
+    // one after the other: concurrent `git worktree add` race on the lock of
+    // the repo.
+    let mut checkouts = Vec::new();
+    let mut result = workers.iter().try_for_each(|dir| {
+        repo.add_checkout(dir)?;
+        checkouts.push(dir.as_path());
+        Ok(())
+    });
+    let mut outcomes = vec![CommandOutput::default(); steps.len()];
+    if result.is_ok() {
+        result = run_workers(repo, &checkouts, steps, &options.command).map(|done| {
+            for (i, outcome) in done {
+                outcomes[i] = outcome;
+            }
+        });
+    }
+    // clean up even if a worker failed.
+    for dir in checkouts {
+        let _ = repo.remove_checkout(dir);
+    }
+    let _ = std::fs::remove_dir_all(&base);
+    result?;
+    Ok(outcomes)
+}
+
+/// Runs `command` at `steps` with a worker in each of `checkouts`. Returns the
+/// outcomes with the index of their step.
+fn run_workers<T: CommitResolver + Sync>(
+    repo: &T,
+    checkouts: &[&Path],
+    steps: &[Step],
+    command: &str,
+) -> Result<Vec<(usize, CommandOutput)>> {
+    let next = AtomicUsize::new(0);
+    let results: Vec<_> = std::thread::scope(|s| {
+        let handles: Vec<_> = checkouts
+            .iter()
+            .map(|dir| {
+                s.spawn(|| -> Result<Vec<(usize, CommandOutput)>> {
+                    let mut outcomes = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::SeqCst);
+                        let Some(step) = steps.get(i) else {
+                            return Ok(outcomes);
+                        };
+                        outcomes.push((i, repo.run_in_checkout(dir, &step.hash, command)?));
+                    }
+                })
+            })
+            .collect();
+        // join all of them: the scope panics on workers left unjoined.
+        handles.into_iter().map(|h| h.join()).collect()
+    });
+    let mut outcomes = Vec::new();
+    for result in results {
+        outcomes.extend(result.map_err(|payload| Error::WorkerPanicked {
+            message: panic_message(payload.as_ref()),
+        })??);
+    }
+    Ok(outcomes)
+}
+
+/// The message given to `panic!`.
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_default()
+}
@@ -0,0 +1,63 @@
+mod common;
+
+use common::*;
+
+#[test]
+fn blame_finds_the_explaining_marker() {
+    let ws = Workspace::new();
+    ws.commit(
+        "Init",
+        Some("I1"),
+        &[Op::Write("src/main.rs", "fn main() {\n}\n")],
+    );
+    ws.commit(
+        "Add a loop",
+        Some("I2"),
+        &[Op::Write("src/main.rs", "fn main() {\n    loop {}\n}\n")],
+    );
+    ws.commit(
+        "SKIP_EXPLAIN: Add scripts",
+        Some("I3"),
+        &[Op::Write("scripts/check.sh", "#!/bin/sh\n")],
+    );
+    ws.commit(
+        "Format",
+        None,
+        &[Op::Write("scripts/fmt.sh", "#!/bin/sh\n")],
+    );
+    ws.commit(
+        "Halt",
+        Some("I5"),
+        &[Op::Write(
+            "src/main.rs",
+            "fn main() {\n    loop {}\n    // unreachable\n}\n",
+        )],
+    );
+    ws.write(
+        "ch01.md",
+        "<!-- ajimi::code change_id I1 -->\n<!-- ajimi::code change_id I5 -->\n",
+    );
+    let blame = |location: &str| {
+        let output = ws.ajimi("blame", &[location, "ch01.md"]);
+        assert!(output.status.success(), "{output:?}");
+        stdout(&output)
+    };
+    assert_eq!(
+        blame("src/main.rs:1"),
+        "src/main.rs:1 comes from I1 \"Init\" (ch01.md:1)\n"
+    );
+    assert_eq!(
+        blame("src/main.rs:2"),
+        "src/main.rs:2 comes from I2 \"Add a loop\", which is not in the book
+  insert it after I1 \"Init\" (ch01.md:1) and before I5 \"Halt\" (ch01.md:2)
+"
+    );
+    assert_eq!(
+        blame("scripts/check.sh:1"),
+        "scripts/check.sh:1 comes from I3 \"SKIP_EXPLAIN: Add scripts\", which is skipped (title contains \"SKIP_EXPLAIN: \")\n"
+    );
+    assert!(blame("scripts/fmt.sh:1").ends_with(", which has no Change-Id\n"));
+
+    let output = ws.ajimi("blame", &["src/main.rs:9", "ch01.md"]);
+    assert!(!output.status.success());
+}
@@ -0,0 +1,154 @@
+mod common;
+
+use common::*;
+
+fn sample_repo() -> Workspace {
+    let ws = Workspace::new();
+    ws.commit(
+        "Init",
+        Some("I1"),
+        &[Op::Write("src/main.rs", "fn main() {}\n")],
+    );
+    ws.commit(
+        "Commit without Change-Id",
+        None,
+        &[Op::Write("README", "x\n")],
+    );
+    ws.commit(
+        "Add a loop",
+        Some("I2"),
+        &[Op::Write("src/main.rs", "fn main() {\n    loop {}\n}\n")],
+    );
+    ws.commit(
+        "SKIP_EXPLAIN: Add scripts",
+        Some("I3"),
+        &[Op::Write("scripts/check.sh", "#!/bin/sh\n")],
+    );
+    ws.commit(
+        "Move main",
+        Some("I4"),
+        &[Op::Rename("src/main.rs", "src/bin/main.rs")],
+    );
+    ws
+}
+
+/// Runs check on `book` and returns (success, stdout).
+fn check(ws: &Workspace, book: &str) -> (bool, String) {
+    ws.write("book.md", book);
+    let output = ws.ajimi("check", &["book.md"]);
+    (output.status.success(), stdout(&output))
+}
+
+#[test]
+fn check_passes_when_all_changes_are_explained() {
+    let ws = sample_repo();
+    let (ok, out) = check(
+        &ws,
+        "<!-- ajimi::code change_id I1 -->
+<!-- ajimi::code change_id I2 -->
+<!-- ajimi::code change_id I4 -->
+",
+    );
+    assert!(ok, "{out}");
+    assert_golden("check_pass.txt", &out);
+}
+
+#[test]
+fn check_reports_reordered_changes() {
+    let ws = sample_repo();
+    let (ok, out) = check(
+        &ws,
+        "<!-- ajimi::code change_id I2 -->
+<!-- ajimi::code change_id I1 -->
+<!-- ajimi::code change_id I4 -->
+",
+    );
+    assert!(!ok);
+    assert_golden("check_reorder.txt", &out);
+}
+
+#[test]
+fn check_reports_missing_and_unknown_ids() {
+    let ws = sample_repo();
+    let (ok, out) = check(
+        &ws,
+        "<!-- ajimi::code change_id I1 -->
+<!-- ajimi::code change_id I9 -->
+<!-- ajimi::code change_id I4 -->
+",
+    );
+    assert!(!ok);
+    assert_golden("check_missing.txt", &out);
+}
+
+#[test]
+fn check_honours_skip_rules() {
+    let ws = sample_repo();
+    ws.commit_message(
+        "Tweak the CI\n\nAjimi-Skip: true\nChange-Id: I5\n",
+        &[Op::Write(".ci.yml", "x\n")],
+    );
+    ws.commit(
+        "Add more scripts",
+        Some("I6"),
+        &[Op::Write("scripts/run.sh", "#!/bin/sh\n")],
+    );
+    ws.commit(
+        "Fix typo",
+        Some("I7"),
+        &[Op::Write(
+            "src/bin/main.rs",
+            "fn main() {\n    loop {}\n}\n\n",
+        )],
+    );
+    ws.write(
+        "ajimi.toml",
+        "[skip]
+paths = [\"scripts/**\"]
+list = \"skip.txt\"
+",
+    );
+    ws.write("skip.txt", "# typos\nI7\n");
+    ws.write(
+        "book.md",
+        "<!-- ajimi::code change_id I1 -->
+<!-- ajimi::code change_id I2 -->
+<!-- ajimi::code change_id I4 -->
+",
+    );
+    let output = ws.ajimi("check", &["--verbose", "book.md"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_golden("check_skip_rules.txt", &stdout(&output));
+}
+
+#[test]
+fn check_reports_stale_titles() {
+    let ws = sample_repo();
+    ws.write(
+        "book.md",
+        "<!-- ajimi::code change_id I1 -->
+<!-- ajimi::code change_id I2 -->
+<!-- ajimi::code change_id I4 -->
+",
+    );
+    let output = ws.ajimi("fix", &["book.md"]);
+    assert!(output.status.success(), "{output:?}");
+    ws.git(&[
+        "commit",
+        "--amend",
+        "-q",
+        "-m",
+        "Move main into src/bin\n\nChange-Id: I4\n",
+    ]);
+    let output = ws.ajimi("check", &["book.md"]);
+    assert!(!output.status.success());
+    assert_golden("check_stale_title.txt", &stdout(&output));
+
+    let output = ws.ajimi("fix", &["book.md"]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(ws
+        .read("book.md")
+        .contains("ajimi::meta::title \"Move main into src/bin\""));
+    let output = ws.ajimi("check", &["book.md"]);
+    assert!(output.status.success(), "{output:?}");
+}
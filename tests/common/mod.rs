@@ -0,0 +1,128 @@
+//! Throwaway git repositories and book files for the integration tests.
+
+// each test binary uses a different part of this module.
+#![allow(dead_code)]
+
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Output;
+use tempfile::TempDir;
+
+/// A change to the tree made by [`Workspace::commit`].
+pub enum Op<'a> {
+    Write(&'a str, &'a str),
+    Remove(&'a str),
+    Rename(&'a str, &'a str),
+}
+
+/// A temporary directory with a git repo in `code/` and book files next to it.
+pub struct Workspace {
+    dir: TempDir,
+}
+impl Workspace {
+    pub fn new() -> Self {
+        let ws = Self {
+            dir: tempfile::tempdir().unwrap(),
+        };
+        std::fs::create_dir(ws.code()).unwrap();
+        ws.git(&["init", "-q"]);
+        ws
+    }
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+    pub fn code(&self) -> PathBuf {
+        self.path().join("code")
+    }
+    pub fn git(&self, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(self.code())
+            .args([
+                "-c",
+                "user.name=ajimi",
+                "-c",
+                "user.email=ajimi@example.com",
+            ])
+            .args(args)
+            .env("GIT_CONFIG_GLOBAL", "/dev/null")
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .env("GIT_AUTHOR_DATE", "2024-01-01T00:00:00Z")
+            .env("GIT_COMMITTER_DATE", "2024-01-01T00:00:00Z")
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "git {args:?}: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).unwrap()
+    }
+    /// Applies `ops` and commits them with a `Change-Id` trailer if given.
+    pub fn commit(&self, title: &str, change_id: Option<&str>, ops: &[Op]) -> String {
+        let message = match change_id {
+            Some(id) => format!("{title}\n\nChange-Id: {id}\n"),
+            None => format!("{title}\n"),
+        };
+        self.commit_message(&message, ops)
+    }
+    /// Applies `ops` and commits them with a full commit message.
+    pub fn commit_message(&self, message: &str, ops: &[Op]) -> String {
+        for op in ops {
+            match op {
+                Op::Write(path, content) => {
+                    let path = self.code().join(path);
+                    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                    std::fs::write(path, content).unwrap();
+                }
+                Op::Remove(path) => {
+                    self.git(&["rm", "-q", path]);
+                }
+                Op::Rename(from, to) => {
+                    std::fs::create_dir_all(self.code().join(to).parent().unwrap()).unwrap();
+                    self.git(&["mv", from, to]);
+                }
+            }
+        }
+        self.git(&["add", "-A"]);
+        self.git(&["commit", "-q", "--allow-empty", "-m", message]);
+        self.git(&["rev-parse", "HEAD"]).trim().to_string()
+    }
+    pub fn write(&self, name: &str, content: &str) {
+        std::fs::write(self.path().join(name), content).unwrap();
+    }
+    pub fn read(&self, name: &str) -> String {
+        std::fs::read_to_string(self.path().join(name)).unwrap()
+    }
+    /// Runs `ajimi <subcommand> --code code <args...>` in the workspace.
+    pub fn ajimi(&self, subcommand: &str, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_ajimi"))
+            .current_dir(self.path())
+            .arg(subcommand)
+            .args(["--code", "code"])
+            .args(args)
+            .env("RUST_BACKTRACE", "0")
+            .output()
+            .unwrap()
+    }
+}
+
+pub fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+/// Compares `actual` with `tests/golden/<name>`. Run with `UPDATE_GOLDEN=1` to
+/// rewrite the golden files instead.
+pub fn assert_golden(name: &str, actual: &str) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(name);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::write(&path, actual).unwrap();
+        return;
+    }
+    let expected = std::fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("{path:?}: {e}. Run with UPDATE_GOLDEN=1 to create it"));
+    assert_eq!(actual, expected, "differs from {path:?}");
+}
@@ -0,0 +1,80 @@
+mod common;
+
+use ajimi::CommitResolver;
+use common::*;
+
+#[test]
+fn coverage_per_file() {
+    let ws = Workspace::new();
+    ws.commit(
+        "Init",
+        Some("I1"),
+        &[Op::Write("src/main.rs", "fn main() {}\n")],
+    );
+    ws.commit(
+        "Add a loop",
+        Some("I2"),
+        &[Op::Write("src/main.rs", "fn main() {\n    loop {}\n}\n")],
+    );
+    ws.commit(
+        "SKIP_EXPLAIN: Add scripts",
+        Some("I3"),
+        &[Op::Write("scripts/check.sh", "#!/bin/sh\n")],
+    );
+    ws.commit(
+        "Add a library",
+        Some("I4"),
+        &[Op::Write("src/lib.rs", "pub fn a() {}\npub fn b() {}\n")],
+    );
+    ws.commit(
+        "Halt",
+        Some("I5"),
+        &[Op::Write("src/main.rs", "fn main() {}\n")],
+    );
+    ws.write("ch01.md", "<!-- ajimi::code change_id I1 -->\n");
+    ws.write("ch02.md", "<!-- ajimi::code change_id I5 -->\n");
+
+    let output = ws.ajimi("coverage", &["ch01.md", "ch02.md"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_golden("coverage.txt", &stdout(&output));
+
+    let output = ws.ajimi("coverage", &["--json", "--top", "1", "ch01.md", "ch02.md"]);
+    assert!(output.status.success(), "{output:?}");
+    let report: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(report["total"]["explained"], 2);
+    assert_eq!(report["total"]["missing"], 2);
+    assert_eq!(report["files"][0]["skipped"], 1);
+    assert_eq!(report["unexplained"][0]["change_id"], "I2");
+}
+
+#[test]
+fn all_commits_in_tree_match_each_commit() {
+    let ws = Workspace::new();
+    ws.commit(
+        "Init",
+        Some("I1"),
+        &[Op::Write("src/main.rs", "fn main() {}\n")],
+    );
+    ws.commit(
+        "Rename",
+        Some("I2"),
+        &[Op::Rename("src/main.rs", "src/bin.rs")],
+    );
+    // not referred to from the book, as it has no Change-Id.
+    ws.commit(
+        "Format",
+        None,
+        &[Op::Write("src/bin.rs", "fn main() {\n}\n")],
+    );
+    ws.commit(
+        "Add a loop",
+        Some("I3"),
+        &[Op::Write("src/bin.rs", "fn main() {\n    loop {}\n}\n")],
+    );
+    let repo = ajimi::GitRepo::new(ws.code());
+    let commits = repo.all_commits_in_tree().unwrap();
+    assert_eq!(commits.len(), 3);
+    for (commit, change_id) in commits.iter().zip(["I3", "I2", "I1"]) {
+        assert_eq!(commit, &repo.commit_from_change_id(change_id).unwrap());
+    }
+}
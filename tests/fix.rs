@@ -0,0 +1,334 @@
+mod common;
+
+use ajimi::CommitResolver;
+use common::*;
+
+const HELLO: &str = "pub fn hello() {
+    println!(\"==========\");
+    println!(\"hello\\n\");
+    println!(\"==========\");
+}
+";
+
+/// Commits with Change-Ids I1..I3, including a rename, and a SKIP_EXPLAIN one.
+fn sample_repo() -> (Workspace, Vec<String>) {
+    let ws = Workspace::new();
+    let hashes = vec![
+        ws.commit(
+            "Init",
+            Some("I1"),
+            &[
+                Op::Write("src/main.rs", "fn main() {\n    hello();\n}\n"),
+                Op::Write("src/hello.rs", HELLO),
+            ],
+        ),
+        ws.commit(
+            "Add a loop",
+            Some("I2"),
+            &[Op::Write(
+                "src/main.rs",
+                "fn main() {\n    hello();\n    loop {}\n}\n",
+            )],
+        ),
+        ws.commit(
+            "Rename hello to greet",
+            Some("I3"),
+            &[
+                Op::Rename("src/hello.rs", "src/greet.rs"),
+                Op::Write(
+                    "src/greet.rs",
+                    &HELLO.replace("hello\\n", "hello, world\\n"),
+                ),
+            ],
+        ),
+        ws.commit(
+            "SKIP_EXPLAIN: Add scripts",
+            Some("I4"),
+            &[Op::Write("scripts/check.sh", "#!/bin/sh\ncargo check\n")],
+        ),
+    ];
+    (ws, hashes)
+}
+
+#[test]
+fn fix_renders_commits() {
+    let (ws, hashes) = sample_repo();
+    ws.write(
+        "book.md",
+        &format!(
+            "# Book
+
+<!-- ajimi::code change_id I1 -->
+
+Some text.
+
+<!-- ajimi::code commit {} -->
+
+<!-- ajimi::code change_id I3 -->
+",
+            hashes[1]
+        ),
+    );
+    let output = ws.ajimi("fix", &["book.md"]);
+    assert!(output.status.success(), "{output:?}");
+    let fixed = ws.read("book.md");
+    assert_golden("fix_renders_commits.md", &fixed);
+
+    // fix is idempotent, and the result passes check.
+    let output = ws.ajimi("fix", &["--dry-run", "book.md"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(ws.read("book.md"), fixed);
+    let output = ws.ajimi("check", &["book.md"]);
+    assert!(output.status.success(), "{output:?}");
+}
+
+#[test]
+fn fix_dry_run_prints_diff() {
+    let (ws, _) = sample_repo();
+    let book = "<!-- ajimi::code change_id I2 -->\n";
+    ws.write("book.md", book);
+    let output = ws.ajimi("fix", &["--dry-run", "book.md"]);
+    assert!(!output.status.success());
+    assert_golden("fix_dry_run.diff", &stdout(&output));
+    assert_eq!(ws.read("book.md"), book);
+}
+
+#[test]
+fn fix_fails_when_markers_are_left_unfixed() {
+    let (ws, _) = sample_repo();
+    ws.write(
+        "book.md",
+        "<!-- ajimi::code change_id I2 -->\n<!-- ajimi::code change_id I9 -->\n",
+    );
+    let output = ws.ajimi("fix", &["book.md"]);
+    assert!(!output.status.success(), "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("I9"));
+    // the markers that could be fixed are written anyway.
+    assert!(ws.read("book.md").contains("loop {}"));
+}
+
+#[test]
+fn commit_ids_are_not_git_options() {
+    let (ws, _) = sample_repo();
+    let repo = ajimi::GitRepo::new(ws.code());
+    assert!(repo.change_id_from_commit_id("--output=log.txt").is_err());
+    assert!(repo.line_from_commit("--output=show.txt", "a", 1).is_err());
+    assert!(!ws.code().join("log.txt").exists());
+    assert!(!ws.code().join("show.txt").exists());
+}
+
+#[test]
+fn fix_keeps_manual_edits() {
+    let (ws, _) = sample_repo();
+    ws.write("book.md", "<!-- ajimi::code change_id I2 -->\n");
+    assert!(ws.ajimi("fix", &["book.md"]).status.success());
+    let edited = ws
+        .read("book.md")
+        .replace("loop {}", "loop { /* edited */ }");
+    ws.write("book.md", &edited);
+    let output = ws.ajimi("fix", &["book.md"]);
+    assert!(!output.status.success());
+    assert_eq!(ws.read("book.md"), edited);
+    assert!(ws.ajimi("fix", &["--force", "book.md"]).status.success());
+    assert!(!ws.read("book.md").contains("edited"));
+}
+
+#[test]
+fn fix_regenerates_books_of_older_versions() {
+    let ws = Workspace::new();
+    ws.commit(
+        "Say \"hi\" -- now",
+        Some("I5"),
+        &[Op::Write(
+            "src/main.rs",
+            "fn main() {\n    hello();\n    println!(\"hi\");\n    loop {}\n}\n",
+        )],
+    );
+    // unescaped title and an end marker without hash, as written before.
+    ws.write(
+        "book.md",
+        "<!-- ajimi::code change_id I5 -->
+<!-- ajimi::meta::title \"Say \"hi\" -- now\" -->
+```rust,noplayground
++    println!(\"hi\");
+```
+<!-- ajimi::end change_id I5 -->
+",
+    );
+    let output = ws.ajimi("check", &["book.md"]);
+    assert!(
+        !stdout(&output).contains("whitespace expected"),
+        "{output:?}"
+    );
+    let output = ws.ajimi("fix", &["book.md"]);
+    assert!(output.status.success(), "{output:?}");
+    let fixed = ws.read("book.md");
+    assert!(
+        fixed.contains("<!-- ajimi::meta::title \"Say \\\"hi\\\" -\\- now\" -->\n"),
+        "{fixed}"
+    );
+    assert!(
+        fixed.contains("<!-- ajimi::end change_id I5 hash="),
+        "{fixed}"
+    );
+    let output = ws.ajimi("check", &["book.md"]);
+    assert!(output.status.success(), "{output:?}");
+}
+
+#[test]
+fn in_memory_repo_renders_like_git() {
+    let (ws, _) = sample_repo();
+    let repo = ajimi::InMemoryRepo::builder()
+        .commit("Init", |c| {
+            c.change_id("I1")
+                .write("src/main.rs", "fn main() {\n    hello();\n}\n")
+                .write("src/hello.rs", HELLO)
+        })
+        .commit("Add a loop", |c| {
+            c.change_id("I2")
+                .write("src/main.rs", "fn main() {\n    hello();\n    loop {}\n}\n")
+        })
+        .commit("Rename hello to greet", |c| {
+            c.change_id("I3")
+                .rename("src/hello.rs", "src/greet.rs")
+                .write(
+                    "src/greet.rs",
+                    &HELLO.replace("hello\\n", "hello, world\\n"),
+                )
+        })
+        .build();
+    ws.write(
+        "book.md",
+        "# Book
+
+<!-- ajimi::code change_id I1 -->
+
+Some text.
+
+<!-- ajimi::code change_id I2 -->
+
+<!-- ajimi::code change_id I3 -->
+",
+    );
+    let mut book = ajimi::Book::load([ws.path().join("book.md")]).unwrap();
+    book.fix(&repo, &ajimi::FixOptions::default()).unwrap();
+    assert_golden(
+        "fix_renders_commits.md",
+        &book.files()[0].document().to_string(),
+    );
+}
+
+/// Asserts that `InMemoryRepo` diffs `before` and `after` as git does. Only
+/// for changes that align one way: when lines can be matched in several ways,
+/// similar and git may pick different ones.
+fn assert_diffs_like_git(before: &str, after: &str, options: &ajimi::DiffOptions) {
+    let ws = Workspace::new();
+    ws.commit("Init", Some("I1"), &[Op::Write("lib.rs", before)]);
+    ws.commit("Change", Some("I2"), &[Op::Write("lib.rs", after)]);
+    let git = ajimi::GitRepo::new(ws.code());
+    let memory = ajimi::InMemoryRepo::builder()
+        .commit("Init", |c| c.change_id("I1").write("lib.rs", before))
+        .commit("Change", |c| c.change_id("I2").write("lib.rs", after))
+        .build();
+    assert_eq!(
+        memory.patch_from_change_id("I2", options).unwrap().files,
+        git.patch_from_change_id("I2", options).unwrap().files,
+        "{before:?} -> {after:?} with {options:?}"
+    );
+}
+
+#[test]
+fn in_memory_repo_counts_hunk_lines_like_git() {
+    // hunks that start or end with a deletion
+    for (before, after) in [
+        (
+            "    x;\n    y;\nfn a() {\n    w;\n",
+            "    y;\n    v;\n\n  z\n#[test]\n",
+        ),
+        ("a\nb\nc\nd\n", "b\nc\n"),
+        ("a\nb\nc\n", "x\na\nc\n"),
+    ] {
+        assert_diffs_like_git(before, after, &ajimi::DiffOptions::default());
+    }
+}
+
+#[test]
+fn in_memory_repo_diffs_with_options_like_git() {
+    let before = "use std::io;\n\n// One.\nfn one() {\n    1;\n    2;\n    3;\n    4;\n}\n\nfn two() {\n    3;\n}\n";
+    let after = before
+        .replace("    3;", "    three;")
+        .replace("    1;", "  1;");
+    let options = [
+        (None, true, None, false),
+        (Some(0), false, None, false),
+        (Some(1), true, None, true),
+        (None, false, Some(ajimi::DiffAlgorithm::Patience), true),
+    ];
+    for (context, function_context, algorithm, ignore_space_change) in options {
+        let options = ajimi::DiffOptions {
+            context,
+            function_context,
+            algorithm,
+            ignore_space_change,
+        };
+        assert_diffs_like_git(before, &after, &options);
+    }
+}
+
+#[test]
+fn in_memory_repo_widens_deletions_like_git() {
+    let options = ajimi::DiffOptions {
+        context: Some(1),
+        function_context: true,
+        ..Default::default()
+    };
+    for (before, after) in [
+        (
+            "#[test]\n// c\nfn a() {\n    x;\n\n  z\nfn b() {\n  w\n",
+            "// c\n  z\n  y\nstruct S;\n}\n",
+        ),
+        ("fn a() {\n    x;\n}\n\nfn b() {\n    y;\n}\n", ""),
+        (
+            "fn a() {\n    x;\n}\n\nfn b() {\n    y;\n}\n",
+            "fn b() {\n    y;\n}\n",
+        ),
+        (
+            "// a\nfn a() {\n    x;\n    y;\n}\n// b\nfn b() {\n    z;\n}\n",
+            "// a\nfn a() {\n}\n// b\nfn b() {\n}\n",
+        ),
+        (
+            "fn a() {\n    x;\n}\nfn b() {\n    y;\n}\nfn c() {\n}\n",
+            "fn c() {\n}\n",
+        ),
+    ] {
+        assert_diffs_like_git(before, after, &options);
+    }
+}
+
+#[test]
+fn fix_inserts_missing_markers() {
+    let (ws, _) = sample_repo();
+    ws.commit_message(
+        "Say goodbye\n\nPrint a message before looping forever.\n\nChange-Id: I5\n",
+        &[Op::Write(
+            "src/main.rs",
+            "fn main() {\n    hello();\n    println!(\"bye\");\n    loop {}\n}\n",
+        )],
+    );
+    ws.write(
+        "book.md",
+        "# Book
+
+<!-- ajimi::code change_id I1 -->
+
+Renaming.
+
+<!-- ajimi::code change_id I3 -->
+",
+    );
+    let output = ws.ajimi("fix", &["--insert-missing", "book.md"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_golden("fix_insert_missing.md", &ws.read("book.md"));
+    let output = ws.ajimi("check", &["book.md"]);
+    assert!(output.status.success(), "{output:?}");
+}
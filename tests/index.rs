@@ -0,0 +1,52 @@
+mod common;
+
+use common::*;
+
+#[test]
+fn index_maps_changes_to_headings() {
+    let ws = Workspace::new();
+    ws.commit(
+        "Init",
+        Some("I1"),
+        &[Op::Write("src/main.rs", "fn main() {}\n")],
+    );
+    ws.commit(
+        "Add a loop",
+        Some("I2"),
+        &[Op::Write("src/main.rs", "fn main() {\n    loop {}\n}\n")],
+    );
+    ws.write(
+        "ch01.md",
+        "# Getting started
+
+<!-- ajimi::code change_id I1 -->
+
+## Spin, forever
+
+<!-- ajimi::code change_id I2 -->
+<!-- ajimi::code change_id I9 -->
+",
+    );
+
+    let output = ws.ajimi("index", &["--csv", "ch01.md"]);
+    assert!(output.status.success(), "{output:?}");
+    let hash = |rev: &str| ws.git(&["rev-parse", rev]).trim().to_string();
+    assert_eq!(
+        stdout(&output),
+        format!(
+            "change_id,hash,file,line,heading,anchor
+I1,{},ch01.md,3,Getting started,getting-started
+I2,{},ch01.md,7,\"Spin, forever\",spin-forever
+I9,,ch01.md,8,\"Spin, forever\",spin-forever
+",
+            hash("HEAD~"),
+            hash("HEAD")
+        )
+    );
+
+    let output = ws.ajimi("index", &["ch01.md"]);
+    assert!(output.status.success(), "{output:?}");
+    let index: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(index[1]["anchor"], "spin-forever");
+    assert_eq!(index[2]["hash"], serde_json::Value::Null);
+}
@@ -0,0 +1,45 @@
+mod common;
+
+use common::*;
+
+#[test]
+fn check_suggests_candidates_and_fix_remaps() {
+    let ws = Workspace::new();
+    ws.commit(
+        "Init",
+        Some("I1"),
+        &[Op::Write("src/main.rs", "fn main() {}\n")],
+    );
+    ws.commit(
+        "Add a loop",
+        Some("I2"),
+        &[Op::Write("src/main.rs", "fn main() {\n    loop {}\n}\n")],
+    );
+    ws.write(
+        "book.md",
+        "<!-- ajimi::code change_id I1 -->\n<!-- ajimi::code change_id I2 -->\n",
+    );
+    let output = ws.ajimi("fix", &["book.md"]);
+    assert!(output.status.success(), "{output:?}");
+
+    // the history is rebuilt without keeping the Change-Id.
+    ws.git(&[
+        "commit",
+        "--amend",
+        "-q",
+        "-m",
+        "Add an infinite loop\n\nChange-Id: I2b\n",
+    ]);
+    let output = ws.ajimi("check", &["book.md"]);
+    assert!(!output.status.success());
+    assert_golden("check_remap_candidates.txt", &stdout(&output));
+
+    ws.write("remap.txt", "# old new\nI2 I2b\n");
+    let output = ws.ajimi("fix", &["--remap", "remap.txt", "book.md"]);
+    assert!(output.status.success(), "{output:?}");
+    let book = ws.read("book.md");
+    assert!(book.contains("<!-- ajimi::code change_id I2b -->"));
+    assert!(book.contains("<!-- ajimi::end change_id I2b hash="));
+    let output = ws.ajimi("check", &["book.md"]);
+    assert!(output.status.success(), "{output:?}");
+}
@@ -0,0 +1,69 @@
+mod common;
+
+use common::*;
+
+#[test]
+fn run_output_is_embedded_cached_and_checked() {
+    let ws = Workspace::new();
+    ws.commit("Init", Some("I1"), &[Op::Write("msg.sh", "hello\n")]);
+    ws.write(
+        "book.md",
+        "<!-- ajimi::code change_id I1 -->
+<!-- ajimi::run change_id I1 cmd=\"cat msg.sh\" -->
+",
+    );
+    ws.write("ajimi.toml", "[run]\ncache_dir = \"cache\"\n");
+    let output = ws.ajimi("check", &["book.md"]);
+    assert!(!output.status.success());
+    assert!(stdout(&output).contains("I1: output of \"cat msg.sh\" is not generated yet; run fix"));
+
+    // commands are only run with --run, and never with --dry-run. fix fails
+    // as the output is left out of date.
+    let output = ws.ajimi("fix", &["book.md"]);
+    assert!(!output.status.success(), "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("run fix with --run"));
+    assert!(!ws.read("book.md").contains("```txt"));
+    let output = ws.ajimi("fix", &["--dry-run", "--run", "book.md"]);
+    assert!(!output.status.success(), "{output:?}");
+    assert!(!ws.path().join("cache").exists());
+
+    let output = ws.ajimi("fix", &["--run", "book.md"]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(ws.read("book.md").contains("```txt\nhello\n```\n"));
+    let output = ws.ajimi("check", &["book.md"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(ws.git(&["worktree", "list"]).lines().count(), 1);
+
+    // the commit is amended, so the output drifts.
+    ws.write("code/msg.sh", "bye\n");
+    ws.git(&["commit", "--amend", "-q", "-a", "--no-edit"]);
+    let output = ws.ajimi("check", &["book.md"]);
+    assert!(!output.status.success());
+    assert!(stdout(&output).contains("I1: output of \"cat msg.sh\" was captured at "));
+
+    ws.write(
+        "ajimi.toml",
+        "[run]\nenabled = true\ncache_dir = \"cache\"\n",
+    );
+    let output = ws.ajimi("fix", &["book.md"]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(ws.read("book.md").contains("```txt\nbye\n```\n"));
+
+    // the output is read from the cache rather than by running cat again,
+    // even when running commands is not enabled.
+    ws.write("ajimi.toml", "[run]\ncache_dir = \"cache\"\n");
+    let cached: Vec<_> = std::fs::read_dir(ws.path().join("cache"))
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| std::fs::read_to_string(p).unwrap() == "bye\n")
+        .collect();
+    assert_eq!(cached.len(), 1);
+    std::fs::write(&cached[0], "cached\n").unwrap();
+    ws.write(
+        "book.md",
+        "<!-- ajimi::run change_id I1 cmd=\"cat msg.sh\" -->\n",
+    );
+    let output = ws.ajimi("fix", &["book.md"]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(ws.read("book.md").contains("```txt\ncached\n```\n"));
+}
@@ -0,0 +1,41 @@
+mod common;
+
+use common::*;
+
+#[test]
+fn scaffold_after_the_last_explained_commit() {
+    let ws = Workspace::new();
+    ws.commit(
+        "Init",
+        Some("I1"),
+        &[Op::Write("src/main.rs", "fn main() {}\n")],
+    );
+    let second = ws.commit_message(
+        "Add a loop\n\nSpin forever so that the OS does not exit.\n\nChange-Id: I2\n",
+        &[Op::Write("src/main.rs", "fn main() {\n    loop {}\n}\n")],
+    );
+    ws.commit(
+        "SKIP_EXPLAIN: Add scripts",
+        Some("I3"),
+        &[Op::Write("scripts/check.sh", "#!/bin/sh\n")],
+    );
+    ws.commit(
+        "Halt",
+        Some("I4"),
+        &[Op::Write("src/main.rs", "fn main() {}\n")],
+    );
+    ws.write("ch01.md", "<!-- ajimi::code change_id I1 -->\n");
+
+    let output = ws.ajimi("scaffold", &["ch01.md"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_golden("scaffold.md", &stdout(&output));
+
+    // an explicit range, written to a file that must not exist yet.
+    let range = format!("{second}..");
+    let output = ws.ajimi("scaffold", &["--range", &range, "-o", "ch02.md"]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(ws.read("ch02.md").contains("## Halt"));
+    assert!(!ws.read("ch02.md").contains("## Add a loop"));
+    let output = ws.ajimi("scaffold", &["--range", &range, "-o", "ch02.md"]);
+    assert!(!output.status.success());
+}
@@ -0,0 +1,66 @@
+mod common;
+
+use common::*;
+
+fn sample_book() -> Workspace {
+    let ws = Workspace::new();
+    ws.commit("Init", Some("I1"), &[Op::Write("step", "pass\n")]);
+    ws.commit("Break it", Some("I2"), &[Op::Write("step", "fail\n")]);
+    ws.commit("Fix it", Some("I3"), &[Op::Write("step", "pass\n")]);
+    ws.write(
+        "book.md",
+        "<!-- ajimi::code change_id I1 -->
+<!-- ajimi::code change_id I2 -->
+<!-- ajimi::code change_id I3 -->
+",
+    );
+    ws.write("ajimi.toml", "[verify]\ncommand = \"grep pass step\"\n");
+    ws
+}
+
+#[test]
+fn verify_commits_reports_broken_steps() {
+    let ws = sample_book();
+    let output = ws.ajimi("verify-commits", &["--jobs", "2", "book.md"]);
+    assert!(!output.status.success());
+    assert_golden("verify_commits.txt", &stdout(&output));
+    // worktrees are removed.
+    assert_eq!(ws.git(&["worktree", "list"]).lines().count(), 1);
+
+    let output = ws.ajimi("verify-commits", &["--resume-from", "I3", "book.md"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(stdout(&output), "ok   I3 \"Fix it\" (book.md:3)\n");
+
+    let output = ws.ajimi("verify-commits", &["--command", "test -f step", "book.md"]);
+    assert!(output.status.success(), "{output:?}");
+}
+
+#[test]
+fn verify_commits_with_in_memory_repo() {
+    let ws = Workspace::new();
+    ws.write(
+        "book.md",
+        "<!-- ajimi::code change_id I1 -->
+<!-- ajimi::code change_id I2 -->
+<!-- ajimi::code change_id I3 -->
+",
+    );
+    let repo = ajimi::InMemoryRepo::builder()
+        .commit("Init", |c| c.change_id("I1").write("step", "pass\n"))
+        .commit("Break it", |c| {
+            c.change_id("I2").write("step", "fail\n").write("extra", "")
+        })
+        .commit("Fix it", |c| {
+            c.change_id("I3").write("step", "pass\n").remove("extra")
+        })
+        .build();
+    let book = ajimi::Book::load([ws.path().join("book.md")]).unwrap();
+    let steps = ajimi::verify::book_steps(&book, &repo).unwrap();
+    let options = ajimi::verify::VerifyOptions {
+        command: "grep pass step && test ! -f extra".to_string(),
+        jobs: 1,
+    };
+    let outcomes = ajimi::verify::verify_commits(&repo, &steps, &options).unwrap();
+    let success: Vec<bool> = outcomes.iter().map(|o| o.success).collect();
+    assert_eq!(success, [true, false, true]);
+}